@@ -0,0 +1,54 @@
+use iai::criterion::BatchSize;
+use iai::criterion::BenchmarkId;
+use iai::criterion::Criterion;
+use iai::criterion::black_box;
+use iai::criterion::criterion_group;
+use iai::criterion::criterion_main;
+use std::time::Duration;
+
+fn fibonacci(n: u64) -> u64 {
+    match n {
+        0 | 1 => 1,
+        n => fibonacci(n - 1) + fibonacci(n - 2),
+    }
+}
+
+fn bench_fibonacci(c: &mut Criterion) {
+    c.bench_function("fibonacci", |b| b.iter(|| fibonacci(black_box(10))));
+
+    let mut group = c.benchmark_group("fibonacci_with_input");
+    group.sample_size(10);
+    for n in [5, 10, 15] {
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+            b.iter(|| fibonacci(black_box(n)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_sort(c: &mut Criterion) {
+    c.bench_function("sort", |b| {
+        b.iter_batched(
+            || (0..10 * 1024).rev().collect::<Vec<u64>>(),
+            |mut vec| {
+                black_box(&mut vec).sort();
+                vec
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_with_input(BenchmarkId::new("sort_ref", 1024), &1024, |b, &len| {
+        b.iter_batched_ref(
+            || (0..len).rev().collect::<Vec<u64>>(),
+            |vec| black_box(vec).sort(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(1));
+    targets = bench_fibonacci, bench_sort
+);
+criterion_main!(benches);
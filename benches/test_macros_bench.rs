@@ -0,0 +1,39 @@
+use iai::Iai;
+use std::hint::black_box;
+
+fn fibonacci(n: u64) -> u64 {
+    match n {
+        0 | 1 => 1,
+        n => fibonacci(n - 1) + fibonacci(n - 2),
+    }
+}
+
+#[iai::bench]
+fn bench_fibonacci(iai: &mut Iai) {
+    iai.run(|| fibonacci(black_box(10)));
+}
+
+#[iai::bench(args = [5, 10, 15])]
+fn bench_fibonacci_with_input(iai: &mut Iai, n: u64) {
+    iai.run(|| fibonacci(black_box(n)));
+}
+
+fn reversed(len: u64) -> Vec<u64> {
+    (0..len).rev().collect()
+}
+
+fn reversed_10k() -> Vec<u64> {
+    reversed(10 * 1024)
+}
+
+#[iai::bench(setup = reversed_10k)]
+fn bench_sort(iai: &mut Iai, mut vec: Vec<u64>) {
+    iai.run(|| black_box(&mut vec).sort());
+}
+
+#[iai::bench(args = [100, 1000], setup = reversed)]
+fn bench_sort_with_input(iai: &mut Iai, mut vec: Vec<u64>) {
+    iai.run(|| black_box(&mut vec).sort());
+}
+
+iai::main!();
@@ -1,4 +1,9 @@
+use iai::CacheGeometry;
+use iai::Command;
+use iai::Config;
+use iai::CycleModel;
 use iai::Iai;
+use iai::Tool;
 use std::hint::black_box;
 
 fn fibonacci(n: u64) -> u64 {
@@ -20,6 +25,10 @@ fn bench_fibonacci_long(iai: &mut Iai) {
     iai.run(|| fibonacci(black_box(30)));
 }
 
+fn bench_fibonacci_with_input(iai: &mut Iai, n: u64) {
+    iai.run(|| fibonacci(black_box(n)));
+}
+
 fn bench_binary_search(iai: &mut Iai) {
     const LEN: usize = 10 * 1024;
     static LARGE_ARRAY: [u64; LEN] = const {
@@ -50,10 +59,93 @@ fn bench_binary_search_with_allocation(iai: &mut Iai) {
     .expect("number not found");
 }
 
+fn bench_sort_with_setup(iai: &mut Iai) {
+    iai.run_with_setup(
+        || (0..10 * 1024).rev().collect::<Vec<u64>>(),
+        |mut vec| {
+            black_box(&mut vec).sort();
+            vec
+        },
+        |vec| assert!(vec.is_sorted()),
+    );
+}
+
+fn bench_sort_with_setup_ref(iai: &mut Iai) {
+    iai.run_with_setup_ref(
+        || (0..10 * 1024).rev().collect::<Vec<u64>>(),
+        |vec| black_box(vec).sort(),
+        |vec, ()| assert!(vec.is_sorted()),
+    );
+}
+
+fn bench_sort_and_search(iai: &mut Iai) {
+    let mut data = (0..1000).rev().collect::<Vec<u64>>();
+    iai.measure("sort", || data.sort());
+    iai.measure("search", || {
+        for n in 0..100 {
+            black_box(data.binary_search(&black_box(n)).ok());
+        }
+    });
+}
+
+fn bench_sort_command() -> Command {
+    Command::new("sort")
+        .env("LC_ALL", "C")
+        .stdin("banana\napple\ncherry\n")
+}
+
+iai::group!(
+    name = small_cache;
+    config = Config::new()
+        .d1_cache(CacheGeometry::new(4096, 2, 64))
+        .ll_cache(CacheGeometry::new(65536, 4, 64))
+        .cycle_model(CycleModel::new(1, 10, 100));
+    benches = bench_binary_search, bench_binary_search_with_allocation
+);
+
+iai::group!(
+    name = with_callgrind;
+    config = Config::new().tool(Tool::Callgrind);
+    benches = bench_fibonacci, bench_sort_with_setup, bench_sort_and_search
+);
+
+iai::group!(
+    name = with_threads;
+    config = Config::new()
+        .tool(Tool::Callgrind)
+        .separate_threads(true)
+        .trace_children(true);
+    benches = bench_sort_command
+);
+
+iai::group!(
+    name = with_branch_sim;
+    config = Config::new()
+        .branch_sim(true)
+        .cycle_model(CycleModel::DEFAULT.branch_miss(15));
+    benches = bench_binary_search
+);
+
+iai::group!(
+    name = with_dhat;
+    config = Config::new().tool(Tool::Dhat);
+    benches = bench_binary_search_with_allocation, bench_sort_and_search
+);
+
 iai::main!(
     bench_empty,
     bench_fibonacci,
     bench_fibonacci_long,
+    bench_fibonacci_with_input(args = [5, 10, 15]),
     bench_binary_search,
-    bench_binary_search_with_allocation
+    bench_binary_search_with_allocation,
+    bench_sort_with_setup,
+    bench_sort_with_setup_ref,
+    bench_sort_and_search,
+    bench_sort_command,
+    small_cache,
+    with_callgrind,
+    with_threads,
+    with_branch_sim,
+    with_dhat
 );
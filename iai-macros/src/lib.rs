@@ -0,0 +1,148 @@
+//! Procedural macros for [Iai](https://docs.rs/iai). Not meant to be used directly: enable the
+//! `macros` feature of Iai and use the macros re-exported by it instead.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::Expr;
+use syn::ExprArray;
+use syn::ItemFn;
+use syn::Path;
+use syn::meta::ParseNestedMeta;
+use syn::parse_macro_input;
+
+#[derive(Default)]
+struct BenchArgs {
+    args: Option<ExprArray>,
+    setup: Option<Path>,
+    krate: Option<Path>,
+}
+
+impl BenchArgs {
+    fn parse(&mut self, meta: ParseNestedMeta<'_>) -> syn::Result<()> {
+        if meta.path.is_ident("args") {
+            self.args = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("setup") {
+            self.setup = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("crate") {
+            self.krate = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported argument, expected `args`, `setup` or `crate`"))
+        }
+    }
+}
+
+/// Registers a benchmark function, so that it's run by [`iai::main!`] without being listed in it.
+///
+/// The function must have the signature `fn(&mut iai::Iai)`, or `fn(&mut iai::Iai, T)` if it
+/// receives an input. The attribute accepts these optional arguments:
+///
+/// - `args = [...]`: the function is benchmarked once for every input in the list, like with
+///   `iai::main!(my_bench(args = [...]))`.
+/// - `setup = path`: the input of the function is the value returned by the `setup` function,
+///   which receives the input from `args`, if given, and is run outside of the measured region.
+/// - `crate = path`: the path of the Iai crate, if it's not available as `::iai` (for example,
+///   because it's renamed or re-exported by another crate).
+///
+/// Registered benchmarks are run after the ones listed in `iai::main!`, in the order they are
+/// defined in every file. A function must not also be listed in `iai::main!`: benchmarks with the
+/// same name are rejected when the harness starts.
+///
+/// # Examples
+///
+/// ```
+/// use iai::Iai;
+///
+/// fn fibonacci(n: u64) -> u64 {
+///     match n {
+///         0 | 1 => 1,
+///         n => fibonacci(n - 1) + fibonacci(n - 2),
+///     }
+/// }
+///
+/// #[iai::bench]
+/// fn bench_fibonacci(iai: &mut Iai) {
+///     iai.run(|| fibonacci(20));
+/// }
+///
+/// #[iai::bench(args = [10, 15, 20])]
+/// fn bench_fibonacci_with_input(iai: &mut Iai, n: u64) {
+///     iai.run(|| fibonacci(n));
+/// }
+///
+/// fn reversed(len: u64) -> Vec<u64> {
+///     (0..len).rev().collect()
+/// }
+///
+/// #[iai::bench(args = [100, 1000], setup = reversed)]
+/// fn bench_sort(iai: &mut Iai, mut vec: Vec<u64>) {
+///     iai.run(|| vec.sort());
+/// }
+///
+/// iai::main!();
+/// ```
+///
+/// [`iai::main!`]: https://docs.rs/iai/latest/iai/macro.main.html
+#[proc_macro_attribute]
+pub fn bench(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut bench_args = BenchArgs::default();
+    let parser = syn::meta::parser(|meta| bench_args.parse(meta));
+    parse_macro_input!(attr with parser);
+    let func = parse_macro_input!(item as ItemFn);
+    let krate = bench_args
+        .krate
+        .take()
+        .unwrap_or_else(|| syn::parse_quote!(::iai));
+
+    let name = &func.sig.ident;
+    let input = |arg: Option<&Expr>| match (&bench_args.setup, arg) {
+        (Some(setup), Some(arg)) => quote!(, #setup(#arg)),
+        (Some(setup), None) => quote!(, #setup()),
+        (None, Some(arg)) => quote!(, #arg),
+        (None, None) => quote!(),
+    };
+
+    let adds = match &bench_args.args {
+        Some(args) => args
+            .elems
+            .iter()
+            .map(|arg| {
+                let input = input(Some(arg));
+                quote! {
+                    group.add_with_arg(
+                        ::core::stringify!(#name),
+                        ::core::stringify!(#arg),
+                        |iai: &'_ mut #krate::Iai| #name(iai #input),
+                    );
+                }
+            })
+            .collect::<TokenStream2>(),
+        None => {
+            let input = input(None);
+            quote! {
+                group.add(
+                    ::core::stringify!(#name),
+                    |iai: &'_ mut #krate::Iai| #name(iai #input),
+                );
+            }
+        }
+    };
+
+    quote! {
+        #func
+
+        const _: () = {
+            #[#krate::__private::linkme::distributed_slice(#krate::__private::BENCHMARKS)]
+            #[linkme(crate = #krate::__private::linkme)]
+            static REGISTRATION: #krate::__private::Registration = #krate::__private::Registration {
+                file: ::core::file!(),
+                line: ::core::line!(),
+                register: |group: &mut #krate::__private::Group| { #adds },
+            };
+        };
+    }
+    .into()
+}
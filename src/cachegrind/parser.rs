@@ -1,4 +1,5 @@
 use crate::cachegrind::stats::CachegrindStats;
+use crate::cachegrind::stats::FunctionCosts;
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
@@ -8,48 +9,102 @@ use std::io::BufReader;
 use std::num::ParseIntError;
 use std::path::Path;
 
+/// Results parsed from a cachegrind output file.
+#[derive(Clone, Debug)]
+pub(crate) struct CachegrindOutput {
+    /// Total costs.
+    pub(crate) stats: CachegrindStats,
+    /// Costs of every function, in the order they appear in the output file. Functions that
+    /// appear in multiple files (for example because of inlining) have one entry per file.
+    pub(crate) functions: Vec<FunctionCosts>,
+}
+
+/// Parses a cachegrind output file, as described in the [cachegrind output format].
+///
+/// [cachegrind output format]: https://valgrind.org/docs/manual/cg-manual.html#cg-manual.impl-details.file-format
 pub(crate) fn parse_cachegrind_output<P: AsRef<Path>>(
     file: P,
-) -> Result<CachegrindStats, ParseError> {
+) -> Result<CachegrindOutput, ParseError> {
+    let file_in = File::open(file).map_err(ParseError::OpenError)?;
+    parse_cachegrind(BufReader::new(file_in))
+}
+
+/// Parses the contents of a cachegrind output file.
+pub(crate) fn parse_cachegrind<R: BufRead>(reader: R) -> Result<CachegrindOutput, ParseError> {
     let mut events_line = None;
     let mut summary_line = None;
 
-    let file_in = File::open(file).map_err(ParseError::OpenError)?;
+    let mut current_file = String::new();
+    let mut current_function = None;
+    let mut functions: Vec<(String, String, Vec<u64>)> = Vec::new();
+    let mut indexes: HashMap<(String, String), usize> = HashMap::new();
 
-    for line in BufReader::new(file_in).lines() {
+    for (line_number, line) in reader.lines().enumerate() {
         let line = line.map_err(ParseError::ReadError)?;
+        let invalid_line = || ParseError::InvalidLine(line_number + 1);
+
         if let Some(line) = line.strip_prefix("events: ") {
             events_line = Some(line.trim().to_owned());
-        }
-        if let Some(line) = line.strip_prefix("summary: ") {
+        } else if let Some(line) = line.strip_prefix("summary: ") {
             summary_line = Some(line.trim().to_owned());
+        } else if let Some(name) = line
+            .strip_prefix("fl=")
+            .or_else(|| line.strip_prefix("fi="))
+            .or_else(|| line.strip_prefix("fe="))
+        {
+            current_file = name.to_owned();
+        } else if let Some(name) = line.strip_prefix("fn=") {
+            current_function = Some(name.to_owned());
+        } else if line.starts_with(|c: char| c.is_ascii_digit()) {
+            // Cost line: a line number, followed by the counts of each event. Trailing zero
+            // counts may be omitted
+            let costs = line
+                .split_whitespace()
+                .skip(1)
+                .map(|cost| cost.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid_line())?;
+            let function = current_function.clone().ok_or_else(invalid_line)?;
+
+            let index = *indexes
+                .entry((current_file.clone(), function.clone()))
+                .or_insert_with(|| {
+                    functions.push((current_file.clone(), function, Vec::new()));
+                    functions.len() - 1
+                });
+            let total = &mut functions[index].2;
+            if total.len() < costs.len() {
+                total.resize(costs.len(), 0);
+            }
+            for (total, cost) in total.iter_mut().zip(&costs) {
+                *total += cost;
+            }
         }
     }
 
     let events_line = events_line.ok_or(ParseError::EventsNotFound)?;
     let summary_line = summary_line.ok_or(ParseError::SummaryNotFound)?;
 
-    let mut events = HashMap::new();
-    for (key, count) in events_line
+    let events = events_line.split_whitespace().collect::<Vec<_>>();
+    let counts = summary_line
         .split_whitespace()
-        .zip(summary_line.split_whitespace())
-    {
-        let count = count.parse::<u64>().map_err(ParseError::InvalidNumber)?;
-        events.insert(key, count);
-    }
+        .map(|count| count.parse::<u64>().map_err(ParseError::InvalidNumber))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let functions = functions
+        .into_iter()
+        .map(|(file, name, costs)| FunctionCosts {
+            name,
+            file,
+            self_cost: CachegrindStats::from_events(&events, &costs),
+            inclusive: None,
+            calls: Vec::new(),
+        })
+        .collect();
 
-    let get = |key| events.get(key).copied().unwrap_or_default();
-
-    Ok(CachegrindStats {
-        instruction_reads: get("Ir"),
-        instruction_l1_misses: get("I1mr"),
-        instruction_cache_misses: get("ILmr"),
-        data_reads: get("Dr"),
-        data_l1_read_misses: get("D1mr"),
-        data_cache_read_misses: get("DLmr"),
-        data_writes: get("Dw"),
-        data_l1_write_misses: get("D1mw"),
-        data_cache_write_misses: get("DLmw"),
+    Ok(CachegrindOutput {
+        stats: CachegrindStats::from_events(&events, &counts),
+        functions,
     })
 }
 
@@ -60,6 +115,7 @@ pub(crate) enum ParseError {
     EventsNotFound,
     SummaryNotFound,
     InvalidNumber(ParseIntError),
+    InvalidLine(usize),
 }
 
 impl fmt::Display for ParseError {
@@ -72,6 +128,107 @@ impl fmt::Display for ParseError {
             Self::InvalidNumber(err) => {
                 write!(f, "'summary' line contained an invalid number: {err}")
             }
+            Self::InvalidLine(line) => write!(f, "Invalid line {line}"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CachegrindOutput;
+    use super::parse_cachegrind;
+
+    fn parse(output: &str) -> CachegrindOutput {
+        parse_cachegrind(output.as_bytes()).unwrap()
+    }
+
+    const SAMPLE: &str = "\
+desc: I1 cache:         32768 B, 64 B, 8-way associative
+desc: D1 cache:         32768 B, 64 B, 8-way associative
+desc: LL cache:         8388608 B, 64 B, 16-way associative
+cmd: bench --iai-run=bench
+events: Ir I1mr ILmr Dr D1mr DLmr Dw D1mw DLmw
+fl=src/lib.rs
+fn=parse
+1 10 1 1 4 1 0 2
+2 5
+fi=src/inlined.rs
+3 3 0 0 1
+fe=src/lib.rs
+4 2
+fn=sum
+5 7 0 0 2 0 0 1 0 1
+fl=src/other.rs
+fn=parse
+1 20 2 1
+summary: 47 3 2 7 1 0 3 0 1
+";
+
+    #[test]
+    fn totals() {
+        let output = parse(SAMPLE);
+        assert_eq!(output.stats.instruction_reads, 47);
+        assert_eq!(output.stats.instruction_l1_misses, 3);
+        assert_eq!(output.stats.instruction_cache_misses, 2);
+        assert_eq!(output.stats.data_reads, 7);
+        assert_eq!(output.stats.data_l1_read_misses, 1);
+        assert_eq!(output.stats.data_cache_read_misses, 0);
+        assert_eq!(output.stats.data_writes, 3);
+        assert_eq!(output.stats.data_l1_write_misses, 0);
+        assert_eq!(output.stats.data_cache_write_misses, 1);
+        // Branches are only reported with branch simulation
+        assert_eq!(output.stats.conditional_branches, 0);
+    }
+
+    #[test]
+    fn functions_by_file() {
+        let output = parse(SAMPLE);
+        let functions = output
+            .functions
+            .iter()
+            .map(|function| {
+                (
+                    function.file.as_str(),
+                    function.name.as_str(),
+                    function.self_cost.instruction_reads,
+                    function.self_cost.data_reads,
+                    function.self_cost.data_writes,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            functions,
+            [
+                ("src/lib.rs", "parse", 17, 4, 2),
+                ("src/inlined.rs", "parse", 3, 1, 0),
+                ("src/lib.rs", "sum", 7, 2, 1),
+                ("src/other.rs", "parse", 20, 0, 0),
+            ]
+        );
+        assert!(
+            output
+                .functions
+                .iter()
+                .all(|function| { function.inclusive.is_none() && function.calls.is_empty() })
+        );
+    }
+
+    #[test]
+    fn missing_columns_are_zero() {
+        let output = parse(SAMPLE);
+        let other = &output.functions[3];
+        assert_eq!(other.self_cost.instruction_l1_misses, 2);
+        assert_eq!(other.self_cost.instruction_cache_misses, 1);
+        assert_eq!(other.self_cost.data_reads, 0);
+        assert_eq!(other.self_cost.data_cache_write_misses, 0);
+    }
+
+    #[test]
+    fn invalid_output() {
+        assert!(parse_cachegrind("summary: 1\n".as_bytes()).is_err());
+        assert!(parse_cachegrind("events: Ir\n".as_bytes()).is_err());
+        assert!(parse_cachegrind("events: Ir\nfn=f\n1 x\nsummary: 1\n".as_bytes()).is_err());
+        // Cost lines must belong to a function
+        assert!(parse_cachegrind("events: Ir\nfl=a.rs\n1 1\nsummary: 1\n".as_bytes()).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::config::CycleModel;
+
 #[derive(Clone, Debug)]
 pub(crate) struct CachegrindStats {
     pub(crate) instruction_reads: u64,
@@ -9,27 +11,143 @@ pub(crate) struct CachegrindStats {
     pub(crate) data_writes: u64,
     pub(crate) data_l1_write_misses: u64,
     pub(crate) data_cache_write_misses: u64,
+    pub(crate) conditional_branches: u64,
+    pub(crate) conditional_branch_misses: u64,
+    pub(crate) indirect_branches: u64,
+    pub(crate) indirect_branch_misses: u64,
 }
 
 impl CachegrindStats {
+    /// Returns the stats corresponding to the given event names (as found on the `events:` line
+    /// of cachegrind and callgrind output files) and counts. Missing events are set to zero.
+    pub(crate) fn from_events<S: AsRef<str>>(events: &[S], counts: &[u64]) -> Self {
+        let get = |key| {
+            events
+                .iter()
+                .position(|event| event.as_ref() == key)
+                .and_then(|i| counts.get(i))
+                .copied()
+                .unwrap_or_default()
+        };
+
+        CachegrindStats {
+            instruction_reads: get("Ir"),
+            instruction_l1_misses: get("I1mr"),
+            instruction_cache_misses: get("ILmr"),
+            data_reads: get("Dr"),
+            data_l1_read_misses: get("D1mr"),
+            data_cache_read_misses: get("DLmr"),
+            data_writes: get("Dw"),
+            data_l1_write_misses: get("D1mw"),
+            data_cache_write_misses: get("DLmw"),
+            conditional_branches: get("Bc"),
+            conditional_branch_misses: get("Bcm"),
+            indirect_branches: get("Bi"),
+            indirect_branch_misses: get("Bim"),
+        }
+    }
+
     pub(crate) fn ram_accesses(&self) -> u64 {
         self.instruction_cache_misses + self.data_cache_read_misses + self.data_cache_write_misses
     }
 
+    pub(crate) fn branches(&self) -> u64 {
+        self.conditional_branches + self.indirect_branches
+    }
+
+    pub(crate) fn branch_misses(&self) -> u64 {
+        self.conditional_branch_misses + self.indirect_branch_misses
+    }
+
+    fn l3_accesses(&self) -> u64 {
+        self.instruction_l1_misses + self.data_l1_read_misses + self.data_l1_write_misses
+    }
+
+    fn total_memory_rw(&self) -> u64 {
+        self.instruction_reads + self.data_reads + self.data_writes
+    }
+
+    /// Returns the number of hits in every level of the memory hierarchy.
+    ///
+    /// The results of valgrind always have fewer misses than accesses at every level, but after
+    /// subtracting the calibration from them the costs of each level may be clamped differently,
+    /// so the hits are clamped to zero too. [`saturated_by()`](Self::saturated_by) reports when
+    /// this happens.
     pub(crate) fn summarize(&self) -> CachegrindSummary {
         let ram_hits = self.ram_accesses();
-        let l3_accesses =
-            self.instruction_l1_misses + self.data_l1_read_misses + self.data_l1_write_misses;
-        let l3_hits = l3_accesses - ram_hits;
+        let l3_accesses = self.l3_accesses();
+        let l3_hits = l3_accesses.saturating_sub(ram_hits);
 
-        let total_memory_rw = self.instruction_reads + self.data_reads + self.data_writes;
-        let l1_hits = total_memory_rw - (ram_hits + l3_hits);
+        let total_memory_rw = self.total_memory_rw();
+        let l1_hits = total_memory_rw.saturating_sub(ram_hits + l3_hits);
 
         CachegrindSummary {
             l1_hits,
             l3_hits,
             ram_hits,
+            branch_misses: self.branch_misses(),
+        }
+    }
+
+    pub(crate) fn add(&self, other: &CachegrindStats) -> CachegrindStats {
+        CachegrindStats {
+            instruction_reads: self.instruction_reads + other.instruction_reads,
+            instruction_l1_misses: self.instruction_l1_misses + other.instruction_l1_misses,
+            instruction_cache_misses: self.instruction_cache_misses
+                + other.instruction_cache_misses,
+            data_reads: self.data_reads + other.data_reads,
+            data_l1_read_misses: self.data_l1_read_misses + other.data_l1_read_misses,
+            data_cache_read_misses: self.data_cache_read_misses + other.data_cache_read_misses,
+            data_writes: self.data_writes + other.data_writes,
+            data_l1_write_misses: self.data_l1_write_misses + other.data_l1_write_misses,
+            data_cache_write_misses: self.data_cache_write_misses + other.data_cache_write_misses,
+            conditional_branches: self.conditional_branches + other.conditional_branches,
+            conditional_branch_misses: self.conditional_branch_misses
+                + other.conditional_branch_misses,
+            indirect_branches: self.indirect_branches + other.indirect_branches,
+            indirect_branch_misses: self.indirect_branch_misses + other.indirect_branch_misses,
+        }
+    }
+
+    /// Returns the names of the costs lower than the corresponding costs of `calibration`, which
+    /// [`subtract()`](Self::subtract) clamps to zero, followed by the names of the hits that
+    /// [`summarize()`](Self::summarize) clamps to zero once the calibration is subtracted.
+    pub(crate) fn saturated_by(&self, calibration: &CachegrindStats) -> Vec<&'static str> {
+        let mut saturated = self
+            .costs()
+            .into_iter()
+            .zip(calibration.costs())
+            .filter(|((_, cost), (_, calibration))| cost < calibration)
+            .map(|((name, _), _)| name)
+            .collect::<Vec<_>>();
+        let calibrated = self.subtract(calibration);
+        let l3_accesses = calibrated.l3_accesses();
+        if l3_accesses < calibrated.ram_accesses() {
+            saturated.push("l3_hits");
+        }
+        if calibrated.total_memory_rw() < l3_accesses.max(calibrated.ram_accesses()) {
+            saturated.push("l1_hits");
         }
+        saturated
+    }
+
+    /// Returns all the costs, together with their names.
+    fn costs(&self) -> [(&'static str, u64); 13] {
+        [
+            ("instruction_reads", self.instruction_reads),
+            ("instruction_l1_misses", self.instruction_l1_misses),
+            ("instruction_cache_misses", self.instruction_cache_misses),
+            ("data_reads", self.data_reads),
+            ("data_l1_read_misses", self.data_l1_read_misses),
+            ("data_cache_read_misses", self.data_cache_read_misses),
+            ("data_writes", self.data_writes),
+            ("data_l1_write_misses", self.data_l1_write_misses),
+            ("data_cache_write_misses", self.data_cache_write_misses),
+            ("conditional_branches", self.conditional_branches),
+            ("conditional_branch_misses", self.conditional_branch_misses),
+            ("indirect_branches", self.indirect_branches),
+            ("indirect_branch_misses", self.indirect_branch_misses),
+        ]
     }
 
     pub(crate) fn subtract(&self, calibration: &CachegrindStats) -> CachegrindStats {
@@ -57,20 +175,118 @@ impl CachegrindStats {
             data_cache_write_misses: self
                 .data_cache_write_misses
                 .saturating_sub(calibration.data_cache_write_misses),
+            conditional_branches: self
+                .conditional_branches
+                .saturating_sub(calibration.conditional_branches),
+            conditional_branch_misses: self
+                .conditional_branch_misses
+                .saturating_sub(calibration.conditional_branch_misses),
+            indirect_branches: self
+                .indirect_branches
+                .saturating_sub(calibration.indirect_branches),
+            indirect_branch_misses: self
+                .indirect_branch_misses
+                .saturating_sub(calibration.indirect_branch_misses),
         }
     }
 }
 
+/// Costs of a single function.
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionCosts {
+    pub(crate) name: String,
+    pub(crate) file: String,
+    /// Costs of the function itself, excluding its callees.
+    pub(crate) self_cost: CachegrindStats,
+    /// Costs of the function, including its callees. Only available with callgrind.
+    pub(crate) inclusive: Option<CachegrindStats>,
+    /// Calls made by the function. Only available with callgrind.
+    pub(crate) calls: Vec<Call>,
+}
+
+impl FunctionCosts {
+    /// Adds the costs of `other`, which is assumed to be the same function measured in a
+    /// different thread or process.
+    pub(crate) fn merge(&mut self, other: &FunctionCosts) {
+        self.self_cost = self.self_cost.add(&other.self_cost);
+        self.inclusive = match (&self.inclusive, &other.inclusive) {
+            (Some(a), Some(b)) => Some(a.add(b)),
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        for call in &other.calls {
+            match self
+                .calls
+                .iter_mut()
+                .find(|c| c.callee == call.callee && c.callee_file == call.callee_file)
+            {
+                Some(existing) => {
+                    existing.count += call.count;
+                    existing.inclusive = existing.inclusive.add(&call.inclusive);
+                }
+                None => self.calls.push(call.clone()),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Call {
+    pub(crate) callee: String,
+    /// File that contains the code of the callee.
+    pub(crate) callee_file: String,
+    pub(crate) count: u64,
+    /// Costs of the callee, including its own callees, when called from this function.
+    pub(crate) inclusive: CachegrindStats,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct CachegrindSummary {
     pub(crate) l1_hits: u64,
     pub(crate) l3_hits: u64,
     pub(crate) ram_hits: u64,
+    pub(crate) branch_misses: u64,
 }
 
 impl CachegrindSummary {
-    pub(crate) fn cycles(&self) -> u64 {
-        // Uses Itamar Turner-Trauring's formula from https://pythonspeed.com/articles/consistent-benchmarking-in-ci/
-        self.l1_hits + (5 * self.l3_hits) + (35 * self.ram_hits)
+    pub(crate) fn cycles(&self, model: &CycleModel) -> u64 {
+        (model.l1_hit * self.l1_hits)
+            + (model.ll_hit * self.l3_hits)
+            + (model.ram_hit * self.ram_hits)
+            + (model.branch_miss * self.branch_misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachegrindStats;
+
+    const EVENTS: [&str; 9] = [
+        "Ir", "I1mr", "ILmr", "Dr", "D1mr", "DLmr", "Dw", "D1mw", "DLmw",
+    ];
+
+    #[test]
+    fn summarize_clamps_inconsistent_calibrated_costs() {
+        let stats = CachegrindStats::from_events(&EVENTS, &[100, 10, 5, 0, 0, 0, 0, 0, 0]);
+        let calibration = CachegrindStats::from_events(&EVENTS, &[98, 9, 0, 0, 0, 0, 0, 0, 0]);
+
+        // 2 instructions read, with 1 L1 miss and 5 LL misses
+        let calibrated = stats.subtract(&calibration);
+        let summary = calibrated.summarize();
+        assert_eq!(summary.ram_hits, 5);
+        assert_eq!(summary.l3_hits, 0);
+        assert_eq!(summary.l1_hits, 0);
+        assert_eq!(stats.saturated_by(&calibration), ["l3_hits", "l1_hits"]);
+    }
+
+    #[test]
+    fn saturated_by_is_empty_for_consistent_costs() {
+        let stats = CachegrindStats::from_events(&EVENTS, &[100, 10, 5, 50, 4, 2, 20, 3, 1]);
+        let calibration = CachegrindStats::from_events(&EVENTS, &[10, 2, 1, 5, 1, 0, 2, 1, 0]);
+
+        assert!(stats.saturated_by(&calibration).is_empty());
+        let summary = stats.subtract(&calibration).summarize();
+        assert_eq!(summary.ram_hits, 7);
+        assert_eq!(summary.l3_hits, 6);
+        assert_eq!(summary.l1_hits, 140);
     }
 }
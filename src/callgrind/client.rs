@@ -0,0 +1,36 @@
+use crate::valgrind::valgrind_request;
+use crate::valgrind::valgrind_request_code;
+use std::ffi::CStr;
+
+#[inline(always)]
+pub(crate) fn toggle_collect() {
+    const REQ: usize = valgrind_request_code(b'C', b'T', 2);
+    valgrind_request(&[REQ, 0, 0, 0, 0, 0]);
+}
+
+#[inline(always)]
+pub(crate) fn start_instrumentation() {
+    const REQ: usize = valgrind_request_code(b'C', b'T', 4);
+    valgrind_request(&[REQ, 0, 0, 0, 0, 0]);
+}
+
+#[inline(always)]
+pub(crate) fn stop_instrumentation() {
+    const REQ: usize = valgrind_request_code(b'C', b'T', 5);
+    valgrind_request(&[REQ, 0, 0, 0, 0, 0]);
+}
+
+/// Writes the costs collected so far to a new output file, and resets them.
+#[inline(always)]
+pub(crate) fn dump_stats() {
+    const REQ: usize = valgrind_request_code(b'C', b'T', 0);
+    valgrind_request(&[REQ, 0, 0, 0, 0, 0]);
+}
+
+/// Writes the costs collected so far to a new output file, marked with the given trigger, and
+/// resets them.
+#[inline(always)]
+pub(crate) fn dump_stats_at(trigger: &CStr) {
+    const REQ: usize = valgrind_request_code(b'C', b'T', 3);
+    valgrind_request(&[REQ, trigger.as_ptr() as usize, 0, 0, 0, 0]);
+}
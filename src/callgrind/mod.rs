@@ -0,0 +1,13 @@
+mod client;
+mod parser;
+
+pub(crate) use client::dump_stats;
+pub(crate) use client::dump_stats_at;
+pub(crate) use client::start_instrumentation;
+pub(crate) use client::stop_instrumentation;
+pub(crate) use client::toggle_collect;
+pub(crate) use parser::parse_callgrind_output;
+
+/// Prefix of the trigger of the costs dumped at the end of a region, followed by the name of the
+/// region.
+pub(crate) const REGION_TRIGGER: &str = "iai-region:";
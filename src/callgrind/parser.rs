@@ -0,0 +1,418 @@
+use crate::cachegrind::CachegrindStats;
+use crate::cachegrind::Call;
+use crate::cachegrind::FunctionCosts;
+use crate::cachegrind::ParseError;
+use crate::callgrind::REGION_TRIGGER;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Results parsed from a callgrind output file.
+#[derive(Clone, Debug)]
+pub(crate) struct CallgrindOutput {
+    /// Total costs, equivalent to the ones reported by cachegrind.
+    pub(crate) stats: CachegrindStats,
+    /// Costs of every function, in the order they appear in the output file.
+    pub(crate) functions: Vec<FunctionCosts>,
+    /// What caused the costs to be written to the file, like `Program termination`.
+    pub(crate) trigger: Option<String>,
+}
+
+impl CallgrindOutput {
+    /// Returns the name of the region whose costs are in the file, if they were dumped at the end
+    /// of a region.
+    pub(crate) fn region(&self) -> Option<&str> {
+        self.trigger
+            .as_deref()?
+            .strip_prefix("Client Request: ")?
+            .strip_prefix(REGION_TRIGGER)
+    }
+}
+
+#[derive(Default)]
+struct FunctionAccumulator {
+    file: String,
+    self_cost: Vec<u64>,
+    /// Calls made by the function, identified by the file and the name of the callee.
+    calls: Vec<(String, String, u64, Vec<u64>)>,
+}
+
+fn accumulate(total: &mut Vec<u64>, costs: &[u64]) {
+    if total.len() < costs.len() {
+        total.resize(costs.len(), 0);
+    }
+    for (total, cost) in total.iter_mut().zip(costs) {
+        *total += cost;
+    }
+}
+
+/// Table of compressed names, as used by callgrind for `fl=(1) name` and `fl=(1)` lines.
+#[derive(Default)]
+struct Names(HashMap<String, String>);
+
+impl Names {
+    fn resolve(&mut self, spec: &str) -> String {
+        let spec = spec.trim();
+        if let Some(rest) = spec.strip_prefix('(') {
+            if let Some((id, name)) = rest.split_once(')') {
+                let name = name.trim();
+                if name.is_empty() {
+                    return self.0.get(id).cloned().unwrap_or_default();
+                }
+                self.0.insert(id.to_owned(), name.to_owned());
+                return name.to_owned();
+            }
+        }
+        spec.to_owned()
+    }
+}
+
+/// Parses a callgrind output file, as described in the [callgrind format specification].
+///
+/// [callgrind format specification]: https://valgrind.org/docs/manual/cl-format.html
+pub(crate) fn parse_callgrind_output<P: AsRef<Path>>(
+    file: P,
+) -> Result<CallgrindOutput, ParseError> {
+    let file_in = File::open(file).map_err(ParseError::OpenError)?;
+    parse_callgrind(BufReader::new(file_in))
+}
+
+/// Parses the contents of a callgrind output file.
+///
+/// Functions are identified by their name and by the file that contains their code, so that
+/// functions with the same name in different files (like `new` or `fmt`) are kept apart. Like with
+/// cachegrind, the costs of code inlined from other files (after `fi=` and `fe=` lines) are
+/// attributed to an entry for the function in the inlined file.
+fn parse_callgrind<R: BufRead>(reader: R) -> Result<CallgrindOutput, ParseError> {
+    let mut events = None;
+    let mut positions = 1;
+    let mut summary = None;
+    let mut totals = None;
+    let mut trigger = None;
+
+    let mut files = Names::default();
+    let mut functions = Names::default();
+
+    let mut accumulators: Vec<(String, FunctionAccumulator)> = Vec::new();
+    let mut indexes: HashMap<(String, String), usize> = HashMap::new();
+
+    // File of the current function, set by `fl=`, and file of the code that follows, which is
+    // different for inlined code
+    let mut function_file = String::new();
+    let mut current_file = String::new();
+    let mut current_function = None;
+    let mut callee_file = None;
+    let mut callee = None;
+    let mut pending_call = None;
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(ParseError::ReadError)?;
+        let line = line.trim();
+        let invalid_line = || ParseError::InvalidLine(line_number + 1);
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-' || c == '*') {
+            // Cost line: the positions, followed by the counts of each event. Trailing zero
+            // counts may be omitted
+            let costs = line
+                .split_whitespace()
+                .skip(positions)
+                .map(|cost| cost.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid_line())?;
+
+            let function = current_function.get_or_insert_with(|| "???".to_owned());
+            let index = *indexes
+                .entry((current_file.clone(), function.clone()))
+                .or_insert_with(|| {
+                    accumulators.push((
+                        function.clone(),
+                        FunctionAccumulator {
+                            file: current_file.clone(),
+                            ..Default::default()
+                        },
+                    ));
+                    accumulators.len() - 1
+                });
+            let accumulator = &mut accumulators[index].1;
+
+            match pending_call.take() {
+                Some((file, callee, count)) => {
+                    match accumulator
+                        .calls
+                        .iter_mut()
+                        .find(|(other_file, name, _, _)| *other_file == file && *name == callee)
+                    {
+                        Some((_, _, total_count, total)) => {
+                            *total_count += count;
+                            accumulate(total, &costs);
+                        }
+                        None => accumulator.calls.push((file, callee, count, costs)),
+                    }
+                }
+                None => accumulate(&mut accumulator.self_cost, &costs),
+            }
+            continue;
+        }
+
+        let Some(separator) = line.find([':', '=']) else {
+            return Err(invalid_line());
+        };
+        let (key, value) = (&line[..separator], &line[separator + 1..]);
+
+        if line[separator..].starts_with('=') {
+            match key {
+                "fl" => {
+                    function_file = files.resolve(value);
+                    current_file = function_file.clone();
+                }
+                "fi" | "fe" => current_file = files.resolve(value),
+                "fn" => {
+                    current_function = Some(functions.resolve(value));
+                    current_file = function_file.clone();
+                }
+                "cfi" | "cfl" => callee_file = Some(files.resolve(value)),
+                "cfn" => {
+                    // Without `cfi=` or `cfl=`, the callee is in the same file as the caller
+                    let file = callee_file.take().unwrap_or_else(|| current_file.clone());
+                    callee = Some((file, functions.resolve(value)));
+                }
+                "calls" => {
+                    let count = value
+                        .split_whitespace()
+                        .next()
+                        .and_then(|count| count.parse::<u64>().ok())
+                        .ok_or_else(invalid_line)?;
+                    let (file, callee) = callee.take().ok_or_else(invalid_line)?;
+                    pending_call = Some((file, callee, count));
+                }
+                // Other specifications (like `ob=`, `jump=` and `jcnd=`) are not used
+                _ => {}
+            }
+        } else {
+            let value = value.trim();
+            match key {
+                "events" => {
+                    events = Some(value.split_whitespace().map(str::to_owned).collect());
+                }
+                "positions" => positions = value.split_whitespace().count(),
+                "summary" => summary = Some(value.to_owned()),
+                "totals" => totals = Some(value.to_owned()),
+                "desc" => {
+                    if let Some(value) = value.strip_prefix("Trigger:") {
+                        trigger = Some(value.trim().to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let events: Vec<String> = events.ok_or(ParseError::EventsNotFound)?;
+    let summary = summary.or(totals).ok_or(ParseError::SummaryNotFound)?;
+    let summary = summary
+        .split_whitespace()
+        .map(|count| count.parse::<u64>().map_err(ParseError::InvalidNumber))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let functions = accumulators
+        .into_iter()
+        .map(|(name, accumulator)| {
+            let mut inclusive = accumulator.self_cost.clone();
+            for (file, callee, _, costs) in &accumulator.calls {
+                // The self cost already includes all the recursive invocations of the function,
+                // so recursive calls would be counted twice
+                if *file != accumulator.file || *callee != name {
+                    accumulate(&mut inclusive, costs);
+                }
+            }
+
+            FunctionCosts {
+                self_cost: CachegrindStats::from_events(&events, &accumulator.self_cost),
+                inclusive: Some(CachegrindStats::from_events(&events, &inclusive)),
+                calls: accumulator
+                    .calls
+                    .into_iter()
+                    .map(|(callee_file, callee, count, costs)| Call {
+                        callee,
+                        callee_file,
+                        count,
+                        inclusive: CachegrindStats::from_events(&events, &costs),
+                    })
+                    .collect(),
+                file: accumulator.file,
+                name,
+            }
+        })
+        .collect();
+
+    Ok(CallgrindOutput {
+        stats: CachegrindStats::from_events(&events, &summary),
+        functions,
+        trigger,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallgrindOutput;
+    use super::parse_callgrind;
+    use crate::cachegrind::FunctionCosts;
+
+    fn parse(output: &str) -> CallgrindOutput {
+        parse_callgrind(output.as_bytes()).unwrap()
+    }
+
+    fn function<'a>(output: &'a CallgrindOutput, file: &str, name: &str) -> &'a FunctionCosts {
+        output
+            .functions
+            .iter()
+            .find(|function| function.file == file && function.name == name)
+            .unwrap_or_else(|| panic!("function {name} in {file} not found"))
+    }
+
+    const SAMPLE: &str = "\
+# callgrind format
+version: 1
+creator: callgrind-3.22.0
+pid: 1234
+cmd: bench --iai-run=bench
+part: 1
+
+desc: I1 cache: 32768 B, 64 B, 8-way associative
+desc: Trigger: Program termination
+
+positions: line
+events: Ir Dr Dw
+
+ob=(1) /bench
+fl=(1) src/main.rs
+fn=(1) main
+10 5 2 1
+cfl=(2) src/lib.rs
+cfn=(2) fib
+calls=1 20
+11 100 30 10
+12 3
+
+fl=(2)
+fn=(2)
+20 60 20 5
+cfn=(2)
+calls=2 20
+21 40 10 5
+fi=(3) src/inlined.rs
+22 7 1
+fe=(2)
+23 1
+
+fl=(3)
+fn=(3) new
+30 8 2 2
+
+fl=(4) src/other.rs
+fn=(3)
+40 9 1
++1 1 1 1
+
+totals: 186 37 18
+";
+
+    #[test]
+    fn totals_and_trigger() {
+        let output = parse(SAMPLE);
+        assert_eq!(output.stats.instruction_reads, 186);
+        assert_eq!(output.stats.data_reads, 37);
+        assert_eq!(output.stats.data_writes, 18);
+        assert_eq!(output.trigger.as_deref(), Some("Program termination"));
+        assert_eq!(output.region(), None);
+    }
+
+    #[test]
+    fn name_compression() {
+        let output = parse(SAMPLE);
+        let names = output
+            .functions
+            .iter()
+            .map(|function| (function.file.as_str(), function.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("src/main.rs", "main"),
+                ("src/lib.rs", "fib"),
+                ("src/inlined.rs", "fib"),
+                ("src/inlined.rs", "new"),
+                ("src/other.rs", "new"),
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_and_inclusive_costs() {
+        let output = parse(SAMPLE);
+
+        let main = function(&output, "src/main.rs", "main");
+        assert_eq!(main.self_cost.instruction_reads, 8);
+        assert_eq!(main.self_cost.data_reads, 2);
+        assert_eq!(main.calls.len(), 1);
+        let call = &main.calls[0];
+        assert_eq!(
+            (call.callee_file.as_str(), call.callee.as_str()),
+            ("src/lib.rs", "fib")
+        );
+        assert_eq!(call.count, 1);
+        assert_eq!(call.inclusive.instruction_reads, 100);
+        assert_eq!(call.inclusive.data_writes, 10);
+        assert_eq!(main.inclusive.as_ref().unwrap().instruction_reads, 108);
+
+        // Recursive calls are already part of the self cost
+        let fib = function(&output, "src/lib.rs", "fib");
+        assert_eq!(fib.self_cost.instruction_reads, 61);
+        assert_eq!(fib.calls[0].count, 2);
+        assert_eq!(fib.calls[0].inclusive.instruction_reads, 40);
+        assert_eq!(fib.inclusive.as_ref().unwrap().instruction_reads, 61);
+    }
+
+    #[test]
+    fn inlined_code_and_same_named_functions() {
+        let output = parse(SAMPLE);
+
+        let inlined = function(&output, "src/inlined.rs", "fib");
+        assert_eq!(inlined.self_cost.instruction_reads, 7);
+        assert_eq!(inlined.self_cost.data_reads, 1);
+        assert_eq!(inlined.self_cost.data_writes, 0);
+
+        let new = function(&output, "src/inlined.rs", "new");
+        assert_eq!(new.self_cost.instruction_reads, 8);
+        let other = function(&output, "src/other.rs", "new");
+        assert_eq!(other.self_cost.instruction_reads, 10);
+        assert_eq!(other.self_cost.data_writes, 1);
+    }
+
+    #[test]
+    fn region_dump() {
+        let output = parse(
+            "events: Ir\n\
+             desc: Trigger: Client Request: iai-region:parse\n\
+             fl=(1) a.rs\n\
+             fn=(1) f\n\
+             1 3\n\
+             summary: 3\n",
+        );
+        assert_eq!(output.region(), Some("parse"));
+        assert_eq!(output.stats.instruction_reads, 3);
+    }
+
+    #[test]
+    fn missing_events_or_summary() {
+        assert!(parse_callgrind("summary: 1\n".as_bytes()).is_err());
+        assert!(parse_callgrind("events: Ir\n".as_bytes()).is_err());
+        assert!(parse_callgrind("events: Ir\nfn=f\n1 x\nsummary: 1\n".as_bytes()).is_err());
+    }
+}
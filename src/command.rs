@@ -0,0 +1,103 @@
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// An external command to benchmark.
+///
+/// Unlike benchmark functions, which measure only the closures passed to
+/// [`Iai::run()`](crate::Iai::run), external commands are measured from start to finish. This is
+/// useful to measure command line tools end-to-end.
+///
+/// To benchmark a command, define a function that returns it and list the function in
+/// [`iai::main!`](crate::main!) or [`iai::group!`](crate::group!), like a benchmark function. The
+/// function is called when the benchmark is run, and the command is reported like any other
+/// benchmark.
+///
+/// # Examples
+///
+/// ```
+/// use iai::Command;
+///
+/// # #[allow(dead_code)]
+/// fn bench_sort_lines() -> Command {
+///     // For the binaries of the same package, use `env!("CARGO_BIN_EXE_<name>")`
+///     Command::new("sort")
+///         .arg("--reverse")
+///         .env("LC_ALL", "C")
+///         .stdin("banana\napple\ncherry\n")
+/// }
+///
+/// iai::main!(bench_sort_lines);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Command {
+    pub(crate) program: OsString,
+    pub(crate) args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) current_dir: Option<PathBuf>,
+    pub(crate) stdin: Option<Vec<u8>>,
+}
+
+impl Command {
+    /// Returns a new command that runs `program`.
+    ///
+    /// If `program` is not a path, it's searched in the directories listed in `PATH`.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
+            stdin: None,
+        }
+    }
+
+    /// Adds an argument to pass to the program.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Adds multiple arguments to pass to the program.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets an environment variable for the program.
+    ///
+    /// The environment variables set in the [`Config`](crate::Config) of the benchmark are also
+    /// passed to the program.
+    pub fn env<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the working directory of the program.
+    ///
+    /// By default, the program runs in the working directory of the benchmark harness, which
+    /// for `cargo bench` is the root of the package.
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the data written to the standard input of the program.
+    ///
+    /// By default, the standard input of the program is empty.
+    pub fn stdin<B: Into<Vec<u8>>>(mut self, input: B) -> Self {
+        self.stdin = Some(input.into());
+        self
+    }
+}
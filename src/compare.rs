@@ -0,0 +1,480 @@
+//! Comparison of two directories of saved results, used by `--compare`.
+
+use crate::Args;
+use crate::Config;
+use crate::Error;
+use crate::Tool;
+use crate::UserBenchmarks;
+use crate::registry::BenchmarkFn;
+use crate::registry::BenchmarkId;
+use crate::registry::UserBenchmark;
+use crate::regression::find_regressions;
+use crate::regression::regression_thresholds;
+use crate::regression::report_regressions;
+use crate::report::Reporter;
+use crate::results::Output;
+use crate::results::Stats;
+use crate::results::config_file;
+use crate::results::parse_output;
+use crate::results::parts_dir;
+use crate::results::region_file_name;
+use crate::results::same_measurement;
+use crate::results::stored_measurement;
+use crate::runner::Benchmark;
+use crate::runner::calibration_name;
+use crate::runner::warn_saturated;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+
+/// Compares the results of the benchmarks saved in `old_dir` with the ones saved in `new_dir`,
+/// without running anything. Benchmarks are matched by the names of their results files, so that
+/// benchmarks that are no longer defined are compared too, and the ones whose results are found
+/// in only one of the directories are reported as added or removed.
+pub(crate) fn compare_results(
+    benches: &UserBenchmarks,
+    args: &Args,
+    old_dir: &Path,
+    new_dir: &Path,
+) -> Result<ExitCode, Error> {
+    for dir in [old_dir, new_dir] {
+        if !dir.is_dir() {
+            return Err(Error::io(dir, io::ErrorKind::NotFound.into()));
+        }
+    }
+
+    let mut reporter = Reporter::new(args.output_format);
+    reporter
+        .top_functions(args.top_functions)
+        .markdown_file(args.markdown_report.clone());
+
+    // The benchmarks defined in the harness are reported first, in the order they are defined,
+    // followed by the ones that only have results
+    let (old_results, new_results) = (find_results(old_dir)?, find_results(new_dir)?);
+    let mut ids = benches
+        .iter()
+        .map(UserBenchmark::bench_id)
+        .collect::<Vec<_>>();
+    let mut undefined = old_results
+        .iter()
+        .chain(&new_results)
+        .map(|(id, _)| id.bench_id())
+        .filter(|id| !benches.iter().any(|benchmark| benchmark.bench_id() == *id))
+        .collect::<Vec<_>>();
+    undefined.sort();
+    undefined.dedup();
+    ids.extend(undefined);
+
+    // Benchmarks that are no longer defined are compared as if they had the default
+    // configuration, so only the regression thresholds given on the command line apply to them
+    let default_config = Config::new();
+    let mut regressions = Vec::new();
+    for id in ids.into_iter().filter(|id| args.selects(&id.id())) {
+        let benchmark = benches.iter().find(|benchmark| benchmark.bench_id() == id);
+        let config = benchmark.map_or(&default_config, |benchmark| &benchmark.config);
+        let found = |results: &[(ResultsId, Tool)], tool: Tool| {
+            results
+                .iter()
+                .any(|(other, other_tool)| other.bench_id() == id && *other_tool == tool)
+        };
+
+        // The results may have been saved with a tool other than the configured one, if it was
+        // given on the command line
+        let configured_tool = config.tool.unwrap_or(args.tool.unwrap_or_default());
+        let Some(tool) = [
+            configured_tool,
+            Tool::Cachegrind,
+            Tool::Callgrind,
+            Tool::Dhat,
+        ]
+        .into_iter()
+        .find(|tool| found(&old_results, *tool) || found(&new_results, *tool)) else {
+            continue;
+        };
+
+        let output_file = |region: Option<&str>| {
+            let file_name = match region {
+                // Callgrind stores the results of the regions together with the whole benchmark
+                Some(region) if tool != Tool::Callgrind => region_file_name(id.name, region),
+                _ => id.name.to_owned(),
+            };
+            id.output_dir().join(format!("{tool}.out.{file_name}"))
+        };
+        let exists = |file: &Path| file.exists() || parts_dir(file).exists();
+
+        // External commands are not run by the harness, so their results are not calibrated.
+        // Neither are the results of benchmarks that are no longer defined, since the
+        // calibration that applies to them depends on their configuration
+        let calibration = |dir: &Path| match benchmark.map(|benchmark| &benchmark.func) {
+            Some(BenchmarkFn::Function(_)) if !args.no_calibration => {
+                let allow_aslr = config
+                    .allow_aslr
+                    .unwrap_or(env::var_os("IAI_ALLOW_ASLR").is_some());
+                let name = calibration_name(config, tool, allow_aslr);
+                // Fall back to the name used by previous versions of Iai, like when running
+                // the benchmarks
+                let legacy_name = Benchmark::Calibration.name();
+                [name.as_str(), legacy_name].into_iter().find_map(|name| {
+                    let file = dir.join(format!("{tool}.out.{name}"));
+                    parse_output(tool, &file).ok().map(|output| output.stats)
+                })
+            }
+            _ => None,
+        };
+        // Results are only comparable if the calibration is subtracted from both sides or from
+        // neither of them
+        let (old_calibration, new_calibration) = match (calibration(old_dir), calibration(new_dir))
+        {
+            (Some(_), None) if found(&new_results, tool) => {
+                warn!(
+                    "No calibration found in {} for benchmark {}, comparing the results \
+                         without calibration",
+                    new_dir.display(),
+                    id.id()
+                );
+                (None, None)
+            }
+            (None, Some(_)) if found(&old_results, tool) => {
+                warn!(
+                    "No calibration found in {} for benchmark {}, comparing the results \
+                         without calibration",
+                    old_dir.display(),
+                    id.id()
+                );
+                (None, None)
+            }
+            calibrations => calibrations,
+        };
+
+        let mut regions = Vec::<String>::new();
+        for dir in [old_dir, new_dir] {
+            let mut regions_file = dir.join(output_file(None)).into_os_string();
+            regions_file.push(".regions");
+            if let Ok(names) = fs::read_to_string(&regions_file) {
+                for name in names.lines() {
+                    if !regions.iter().any(|region| region == name) {
+                        regions.push(name.to_owned());
+                    }
+                }
+            }
+        }
+
+        for region in [None]
+            .into_iter()
+            .chain(regions.iter().map(|r| Some(r.as_str())))
+        {
+            let file = output_file(region);
+            let (old_file, new_file) = (old_dir.join(&file), new_dir.join(&file));
+            let load = |file: &Path| -> Result<Option<Output>, Error> {
+                if !exists(file) {
+                    return Ok(None);
+                }
+                let output = parse_output(tool, file)?;
+                Ok(match region {
+                    Some(region) if tool == Tool::Callgrind => output
+                        .regions
+                        .into_iter()
+                        .find(|(name, _)| name == region)
+                        .map(|(_, output)| output),
+                    _ => Some(output),
+                })
+            };
+            let (old, new) = (load(&old_file)?, load(&new_file)?);
+            if old.is_none() && new.is_none() {
+                continue;
+            }
+
+            reporter.print_header(id, region);
+            let Some(new) = new else {
+                reporter.print_removed(id, region);
+                continue;
+            };
+
+            let measurement = stored_measurement(&new_file).ok_or_else(|| Error::Parse {
+                path: config_file(&new_file),
+                line: None,
+                message: "Invalid measurement configuration".to_owned(),
+            })?;
+            let added = old.is_none();
+            let (old, old_functions) = match old {
+                Some(old) if same_measurement(&old_file, &measurement) => {
+                    let stats = match &old_calibration {
+                        Some(calibration) => old.stats.subtract(calibration),
+                        None => old.stats,
+                    };
+                    (Some(stats), old.functions)
+                }
+                Some(_) => {
+                    warn!(
+                        "The configuration of benchmark {} changed, not comparing the results",
+                        id.region_id(region)
+                    );
+                    (None, Vec::new())
+                }
+                None => (None, Vec::new()),
+            };
+
+            let stats = Stats {
+                new: match &new_calibration {
+                    Some(calibration) => {
+                        warn_saturated(&id.region_id(region), &new.stats, calibration);
+                        new.stats.subtract(calibration)
+                    }
+                    None => new.stats,
+                },
+                old,
+                tool,
+                config: measurement,
+                regions: Vec::new(),
+                functions: new.functions,
+                old_functions,
+                parts: new.parts,
+            };
+            let thresholds = regression_thresholds(args, id, config, region);
+            if added {
+                reporter.print_added(id, region, &stats, &thresholds);
+            } else {
+                reporter.print_stats(id, region, &stats, &thresholds);
+            }
+            regressions.extend(find_regressions(args, id, config, region, &stats));
+        }
+    }
+    reporter.finish()?;
+
+    report_regressions(regressions)
+}
+
+/// Benchmark whose results were found in a directory of saved results, which may no longer be
+/// defined in the harness.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ResultsId {
+    group: Option<String>,
+    name: String,
+}
+
+impl ResultsId {
+    fn bench_id(&self) -> BenchmarkId<'_> {
+        BenchmarkId {
+            group: self.group.as_deref(),
+            name: &self.name,
+        }
+    }
+}
+
+/// Returns the benchmarks whose results are saved in `dir`, together with the tool that obtained
+/// them.
+///
+/// The results of ungrouped benchmarks are stored at the top of the directory, and the ones of
+/// the other benchmarks in a subdirectory named after their group. The results of the
+/// calibration, of previous runs and of the regions measured in separate runs are skipped, and so
+/// are the named baselines saved next to the results, like in `target/iai`.
+fn find_results(dir: &Path) -> Result<Vec<(ResultsId, Tool)>, Error> {
+    let mut results = Vec::new();
+    // Directories left to read, with the group they belong to and, for the directories created
+    // by benchmarks with a `/` in their name, the tool and the start of the name
+    let mut pending = vec![(dir.to_owned(), None, None)];
+    while let Some((path, group, prefix)) = pending.pop() {
+        for entry in fs::read_dir(&path).map_err(|err| Error::io(&path, err))? {
+            let entry = entry.map_err(|err| Error::io(&path, err))?;
+            let is_dir = entry
+                .file_type()
+                .map_err(|err| Error::io(entry.path(), err))?
+                .is_dir();
+            // Iai only creates files with UTF-8 names
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+
+            let result = match &prefix {
+                Some((tool, prefix)) => Some((*tool, format!("{prefix}{file_name}"))),
+                None => [Tool::Cachegrind, Tool::Callgrind, Tool::Dhat]
+                    .into_iter()
+                    .find_map(|tool| {
+                        let name = file_name.strip_prefix(&format!("{tool}.out."))?;
+                        Some((tool, name.to_owned()))
+                    }),
+            };
+            let Some((tool, name)) = result else {
+                if is_dir && group.is_none() && prefix.is_none() && !is_baseline_dir(&entry.path())?
+                {
+                    pending.push((entry.path(), Some(file_name), None));
+                }
+                continue;
+            };
+
+            let name = if is_dir {
+                match name.strip_suffix(".parts") {
+                    Some(name) => name.to_owned(),
+                    None if name.ends_with(".dumps") || name.ends_with(".regions.d") => continue,
+                    None => {
+                        pending.push((
+                            entry.path(),
+                            group.clone(),
+                            Some((tool, format!("{name}/"))),
+                        ));
+                        continue;
+                    }
+                }
+            } else if [".config", ".log", ".regions"]
+                .iter()
+                .any(|suffix| name.ends_with(suffix))
+            {
+                continue;
+            } else {
+                name
+            };
+            if name.ends_with(".old")
+                || name.ends_with(".new")
+                || (group.is_none() && name.starts_with(Benchmark::Calibration.name()))
+            {
+                continue;
+            }
+
+            let id = ResultsId {
+                group: group.clone(),
+                name,
+            };
+            if !results.contains(&(id.clone(), tool)) {
+                results.push((id, tool));
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Returns whether a directory found at the top of a directory of results holds a named baseline
+/// saved with `--save-baseline`, rather than the results of a group.
+///
+/// Groups only contain the results of their benchmarks, while baselines also contain the results
+/// of the calibration, which belongs to no group, or the directories of their own groups.
+fn is_baseline_dir(dir: &Path) -> Result<bool, Error> {
+    for entry in fs::read_dir(dir).map_err(|err| Error::io(dir, err))? {
+        let entry = entry.map_err(|err| Error::io(dir, err))?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        let name = [Tool::Cachegrind, Tool::Callgrind, Tool::Dhat]
+            .into_iter()
+            .find_map(|tool| file_name.strip_prefix(&format!("{tool}.out.")));
+        match name {
+            Some(name) if !name.starts_with(Benchmark::Calibration.name()) => {}
+            _ => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_results;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// Returns an empty directory for the results of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("iai-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Creates empty files at the given paths, relative to `dir`, and their parent directories.
+    fn create_files(dir: &Path, paths: &[&str]) {
+        for path in paths {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    /// Returns the results found in `dir`, as `group/name (tool)` strings, sorted.
+    fn results(dir: &Path) -> Vec<String> {
+        let mut results = find_results(dir)
+            .unwrap()
+            .into_iter()
+            .map(|(id, tool)| format!("{} ({tool})", id.bench_id().id()))
+            .collect::<Vec<_>>();
+        results.sort();
+        results
+    }
+
+    #[test]
+    fn groups_and_nested_names() {
+        let dir = test_dir("find-results");
+        create_files(
+            &dir,
+            &[
+                "cachegrind.out.fib",
+                "cachegrind.out.fib.config",
+                "cachegrind.out.fib.log",
+                "cachegrind.out.fib.old",
+                "cachegrind.out.fib.old.config",
+                "cachegrind.out.fib.new",
+                "cachegrind.out.fib.new.config",
+                "cachegrind.out.bisect.new",
+                "cachegrind.out.::iai::calibration-0123456789abcdef",
+                "cachegrind.out.::iai::calibration",
+                "cachegrind.out.sort.regions.d/search/cachegrind.out.sort",
+                "callgrind.out.sort.dumps/out.2",
+                "callgrind.out.fib/10",
+                "callgrind.out.fib/10.config",
+                "callgrind.out.fib/20/x",
+                "callgrind.out.threads.parts/123",
+                "dhat.out.alloc",
+                "history.jsonl",
+                "small_cache/cachegrind.out.search",
+                "small_cache/cachegrind.out.search.log",
+                "small_cache/callgrind.out.criterion/fib::10",
+            ],
+        );
+
+        assert_eq!(
+            results(&dir),
+            [
+                "alloc (dhat)",
+                "fib (cachegrind)",
+                "fib/10 (callgrind)",
+                "fib/20/x (callgrind)",
+                "small_cache/criterion/fib::10 (callgrind)",
+                "small_cache/search (cachegrind)",
+                "threads (callgrind)",
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn baselines_are_skipped() {
+        let dir = test_dir("find-results-baselines");
+        create_files(
+            &dir,
+            &[
+                "cachegrind.out.fib",
+                "small_cache/cachegrind.out.search",
+                // Baseline with ungrouped benchmarks, and its comparison with `--baseline`
+                "main/cachegrind.out.fib",
+                "main/cachegrind.out.fib.new",
+                "main/cachegrind.out.::iai::calibration-0123456789abcdef",
+                "main/cachegrind.out.::iai::calibration-0123456789abcdef.new",
+                // Baseline saved with `--no-calibration`, with only grouped benchmarks
+                "before/small_cache/cachegrind.out.search",
+            ],
+        );
+        assert_eq!(
+            results(&dir),
+            ["fib (cachegrind)", "small_cache/search (cachegrind)"]
+        );
+
+        // The baselines can still be compared themselves
+        assert_eq!(results(&dir.join("main")), ["fib (cachegrind)"]);
+        assert_eq!(
+            results(&dir.join("before")),
+            ["small_cache/search (cachegrind)"]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
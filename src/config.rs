@@ -0,0 +1,576 @@
+use crate::json;
+use crate::metric::Metric;
+use crate::valgrind;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fmt;
+use std::str::FromStr;
+
+/// Configuration shared by a group of benchmarks.
+///
+/// A configuration is assigned to a group of benchmarks using [`iai::group!`](crate::group!), or
+/// to all the benchmarks using [`iai::main!`](crate::main!). Options that are not set explicitly
+/// in a group are taken from the configuration of the enclosing group or of `iai::main!`, and
+/// otherwise use their default values.
+///
+/// # Examples
+///
+/// ```
+/// use iai::CacheGeometry;
+/// use iai::Config;
+/// use iai::CycleModel;
+/// use iai::Metric;
+/// use iai::Tool;
+///
+/// let config = Config::new()
+///     .tool(Tool::Callgrind)
+///     .allow_aslr(true)
+///     .ll_cache(CacheGeometry::new(2 * 1024 * 1024, 16, 64))
+///     .cycle_model(CycleModel::new(1, 10, 100))
+///     .env("RUST_LOG", "off")
+///     .valgrind_arg("--verbose")
+///     .regression_threshold(Metric::Instructions, 5.0);
+/// # let _ = config;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub(crate) tool: Option<Tool>,
+    pub(crate) allow_aslr: Option<bool>,
+    pub(crate) i1_cache: Option<CacheGeometry>,
+    pub(crate) d1_cache: Option<CacheGeometry>,
+    pub(crate) ll_cache: Option<CacheGeometry>,
+    pub(crate) cycle_model: Option<CycleModel>,
+    pub(crate) branch_sim: Option<bool>,
+    pub(crate) trace_children: Option<bool>,
+    pub(crate) separate_threads: Option<bool>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) valgrind_args: Vec<OsString>,
+    pub(crate) regression_thresholds: Vec<(Metric, f64)>,
+}
+
+impl Config {
+    /// Returns a new configuration with all options set to their defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the valgrind tool used to measure the benchmarks.
+    ///
+    /// By default, [`Tool::Cachegrind`] is used, unless a different tool is given on the command
+    /// line with `--tool`.
+    pub fn tool(mut self, tool: Tool) -> Self {
+        self.tool = Some(tool);
+        self
+    }
+
+    /// Sets whether address space layout randomization (ASLR) is allowed when running the
+    /// benchmarks.
+    ///
+    /// By default, ASLR is disabled on the platforms where it's possible to do so, unless the
+    /// `IAI_ALLOW_ASLR` environment variable is set.
+    pub fn allow_aslr(mut self, allow_aslr: bool) -> Self {
+        self.allow_aslr = Some(allow_aslr);
+        self
+    }
+
+    /// Sets the geometry of the simulated L1 instruction cache.
+    ///
+    /// Defaults to 32 KiB, 8-way associative, with 64-byte lines.
+    pub fn i1_cache(mut self, geometry: CacheGeometry) -> Self {
+        self.i1_cache = Some(geometry);
+        self
+    }
+
+    /// Sets the geometry of the simulated L1 data cache.
+    ///
+    /// Defaults to 32 KiB, 8-way associative, with 64-byte lines.
+    pub fn d1_cache(mut self, geometry: CacheGeometry) -> Self {
+        self.d1_cache = Some(geometry);
+        self
+    }
+
+    /// Sets the geometry of the simulated last-level cache.
+    ///
+    /// Defaults to 8 MiB, 16-way associative, with 64-byte lines.
+    pub fn ll_cache(mut self, geometry: CacheGeometry) -> Self {
+        self.ll_cache = Some(geometry);
+        self
+    }
+
+    /// Sets the weights used to estimate the number of CPU cycles from the number of cache hits
+    /// and misses.
+    ///
+    /// Defaults to [`CycleModel::DEFAULT`].
+    pub fn cycle_model(mut self, cycle_model: CycleModel) -> Self {
+        self.cycle_model = Some(cycle_model);
+        self
+    }
+
+    /// Sets whether branch prediction is simulated.
+    ///
+    /// When enabled, the number of branches executed and the number of mispredicted branches
+    /// are included in the reports. Branch simulation is disabled by default, because it makes
+    /// benchmarks slower to run.
+    pub fn branch_sim(mut self, branch_sim: bool) -> Self {
+        self.branch_sim = Some(branch_sim);
+        self
+    }
+
+    /// Sets whether the child processes started by the benchmarks are measured.
+    ///
+    /// When enabled, the costs of the child processes are added to the costs of the benchmark,
+    /// and each process is also reported separately. Child processes are measured from start to
+    /// finish only for [`Command`](crate::Command) benchmarks: in benchmark functions,
+    /// instrumentation is disabled when a process starts, and child processes are not measured
+    /// unless they enable it themselves.
+    pub fn trace_children(mut self, trace_children: bool) -> Self {
+        self.trace_children = Some(trace_children);
+        self
+    }
+
+    /// Sets whether the threads of the benchmarks are reported separately.
+    ///
+    /// When enabled, the costs of each thread are reported alongside the total. Only supported
+    /// by [`Tool::Callgrind`]: Cachegrind always reports all the threads together.
+    pub fn separate_threads(mut self, separate_threads: bool) -> Self {
+        self.separate_threads = Some(separate_threads);
+        self
+    }
+
+    /// Sets an environment variable for the benchmarks.
+    ///
+    /// The environment variables are stored alongside the results, like the cache geometry:
+    /// results obtained with different environment variables are not compared.
+    pub fn env<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Passes an additional command line argument to valgrind.
+    pub fn valgrind_arg<S: AsRef<OsStr>>(mut self, arg: S) -> Self {
+        self.valgrind_args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Passes additional command line arguments to valgrind.
+    pub fn valgrind_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.valgrind_args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the maximum allowed increase of a metric, as a percentage of its baseline value.
+    ///
+    /// If a benchmark exceeds the threshold, it's reported as a regression and the benchmark
+    /// harness exits with a non-zero exit code. Thresholds given on the command line with
+    /// `--regression-threshold <metric>=<percent>` apply to the benchmarks that do not set one
+    /// in their configuration.
+    pub fn regression_threshold(mut self, metric: Metric, percent: f64) -> Self {
+        self.regression_thresholds
+            .retain(|(existing, _)| *existing != metric);
+        self.regression_thresholds.push((metric, percent));
+        self
+    }
+
+    /// Returns a copy of this configuration, with the options that are not set taken from
+    /// `parent`. Environment variables and valgrind arguments from `parent` come before the
+    /// ones from this configuration.
+    pub(crate) fn inherit(&self, parent: &Self) -> Self {
+        let mut regression_thresholds = self.regression_thresholds.clone();
+        for (metric, percent) in &parent.regression_thresholds {
+            if !regression_thresholds.iter().any(|(m, _)| m == metric) {
+                regression_thresholds.push((*metric, *percent));
+            }
+        }
+
+        Self {
+            tool: self.tool.or(parent.tool),
+            allow_aslr: self.allow_aslr.or(parent.allow_aslr),
+            i1_cache: self.i1_cache.or(parent.i1_cache),
+            d1_cache: self.d1_cache.or(parent.d1_cache),
+            ll_cache: self.ll_cache.or(parent.ll_cache),
+            cycle_model: self.cycle_model.or(parent.cycle_model),
+            branch_sim: self.branch_sim.or(parent.branch_sim),
+            trace_children: self.trace_children.or(parent.trace_children),
+            separate_threads: self.separate_threads.or(parent.separate_threads),
+            envs: parent.envs.iter().chain(&self.envs).cloned().collect(),
+            valgrind_args: parent
+                .valgrind_args
+                .iter()
+                .chain(&self.valgrind_args)
+                .cloned()
+                .collect(),
+            regression_thresholds,
+        }
+    }
+
+    /// Returns the options that affect the results of the benchmarks, with the defaults applied.
+    pub(crate) fn measurement(&self) -> MeasurementConfig {
+        MeasurementConfig {
+            i1_cache: self.i1_cache.unwrap_or(valgrind::DEFAULT_I1_CACHE),
+            d1_cache: self.d1_cache.unwrap_or(valgrind::DEFAULT_D1_CACHE),
+            ll_cache: self.ll_cache.unwrap_or(valgrind::DEFAULT_LL_CACHE),
+            cycle_model: self.cycle_model.unwrap_or_default(),
+            branch_sim: self.branch_sim.unwrap_or(false),
+            trace_children: self.trace_children.unwrap_or(false),
+            envs: self
+                .envs
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        key.to_string_lossy().into_owned(),
+                        value.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+            command: None,
+        }
+    }
+}
+
+/// Options that affect the results of the benchmarks.
+///
+/// These options are stored alongside the results, so that results obtained with different
+/// options are never compared with each other. The stored format is one `key=value` pair per
+/// line, with one `env` line for every environment variable, whose value is the JSON string
+/// `"NAME=VALUE"`, and a `command` line for the benchmarks of external commands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MeasurementConfig {
+    pub(crate) i1_cache: CacheGeometry,
+    pub(crate) d1_cache: CacheGeometry,
+    pub(crate) ll_cache: CacheGeometry,
+    pub(crate) cycle_model: CycleModel,
+    pub(crate) branch_sim: bool,
+    pub(crate) trace_children: bool,
+    /// Environment variables set for the benchmarks, as names and values, in the order they are
+    /// set.
+    pub(crate) envs: Vec<(String, String)>,
+    /// Hash of the program, arguments, environment variables, working directory and standard
+    /// input of the external command measured by the benchmark, if any.
+    pub(crate) command: Option<u64>,
+}
+
+impl fmt::Display for MeasurementConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "i1-cache={}", self.i1_cache)?;
+        writeln!(f, "d1-cache={}", self.d1_cache)?;
+        writeln!(f, "ll-cache={}", self.ll_cache)?;
+        writeln!(f, "cycle-model={}", self.cycle_model)?;
+        writeln!(
+            f,
+            "branch-sim={}",
+            if self.branch_sim { "yes" } else { "no" }
+        )?;
+        writeln!(
+            f,
+            "trace-children={}",
+            if self.trace_children { "yes" } else { "no" }
+        )?;
+        for (key, value) in &self.envs {
+            writeln!(f, "env={}", json::Value::from(format!("{key}={value}")))?;
+        }
+        if let Some(command) = self.command {
+            writeln!(f, "command={command:016x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MeasurementConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut i1_cache = None;
+        let mut d1_cache = None;
+        let mut ll_cache = None;
+        let mut cycle_model = None;
+        let mut branch_sim = false;
+        let mut trace_children = false;
+        let mut envs = Vec::new();
+        let mut command = None;
+
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'key=value', got '{line}'"))?;
+            match key {
+                "i1-cache" => i1_cache = Some(value.parse()?),
+                "d1-cache" => d1_cache = Some(value.parse()?),
+                "ll-cache" => ll_cache = Some(value.parse()?),
+                "cycle-model" => cycle_model = Some(value.parse()?),
+                "branch-sim" => branch_sim = value == "yes",
+                "trace-children" => trace_children = value == "yes",
+                "env" => {
+                    let env = value.parse::<json::Value>()?;
+                    let (key, value) = env
+                        .as_str()
+                        .and_then(|env| env.split_once('='))
+                        .ok_or_else(|| format!("expected '\"NAME=VALUE\"', got '{value}'"))?;
+                    envs.push((key.to_owned(), value.to_owned()));
+                }
+                "command" => {
+                    let hash = u64::from_str_radix(value, 16)
+                        .map_err(|err| format!("invalid command hash '{value}': {err}"))?;
+                    command = Some(hash);
+                }
+                _ => return Err(format!("unknown option '{key}'")),
+            }
+        }
+
+        let missing = |key| format!("missing option '{key}'");
+        Ok(Self {
+            i1_cache: i1_cache.ok_or_else(|| missing("i1-cache"))?,
+            d1_cache: d1_cache.ok_or_else(|| missing("d1-cache"))?,
+            ll_cache: ll_cache.ok_or_else(|| missing("ll-cache"))?,
+            cycle_model: cycle_model.ok_or_else(|| missing("cycle-model"))?,
+            branch_sim,
+            trace_children,
+            envs,
+            command,
+        })
+    }
+}
+
+/// Valgrind tool used to measure benchmarks.
+///
+/// Support for more tools may be added in the future, so the enum is non-exhaustive.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Tool {
+    /// Measure benchmarks with [Cachegrind](https://valgrind.org/docs/manual/cg-manual.html).
+    #[default]
+    Cachegrind,
+    /// Measure benchmarks with [Callgrind](https://valgrind.org/docs/manual/cl-manual.html).
+    ///
+    /// Callgrind is slower than Cachegrind, but in addition to the same metrics it also records
+    /// the call graph of the benchmarks, which is included in the reports.
+    Callgrind,
+    /// Measure the heap allocations of benchmarks with
+    /// [DHAT](https://valgrind.org/docs/manual/dh-manual.html).
+    ///
+    /// Instead of instructions and cache accesses, DHAT reports the number of heap allocations,
+    /// the total number of bytes allocated and the peak number of bytes live at the same time.
+    ///
+    /// DHAT cannot be restricted to the code run inside [`Iai::run()`](crate::Iai::run): it
+    /// measures the whole benchmark function, including the code outside of it, minus the
+    /// allocations made by the harness itself. The peak number of bytes live is reported as
+    /// measured, since the peak of the harness cannot be told apart from that of the benchmark.
+    /// For the same reason, regions created with [`Iai::measure()`](crate::Iai::measure) are
+    /// not reported.
+    Dhat,
+}
+
+impl Tool {
+    /// Name of the tool, as accepted by valgrind.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cachegrind => "cachegrind",
+            Self::Callgrind => "callgrind",
+            Self::Dhat => "dhat",
+        }
+    }
+}
+
+impl fmt::Display for Tool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Tool {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Self::Cachegrind, Self::Callgrind, Self::Dhat]
+            .into_iter()
+            .find(|tool| tool.name() == s)
+            .ok_or_else(|| format!("unknown tool '{s}' (expected cachegrind, callgrind or dhat)"))
+    }
+}
+
+/// Geometry of a cache simulated by valgrind.
+///
+/// Refer to the [Cachegrind documentation] for the restrictions on the values accepted.
+///
+/// [Cachegrind documentation]: https://valgrind.org/docs/manual/cg-manual.html#cg-manual.cgopts
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheGeometry {
+    /// Total size of the cache, in bytes.
+    pub size: u64,
+    /// Associativity of the cache.
+    pub associativity: u64,
+    /// Size of a cache line, in bytes.
+    pub line_size: u64,
+}
+
+impl CacheGeometry {
+    /// Returns a new cache geometry.
+    pub const fn new(size: u64, associativity: u64, line_size: u64) -> Self {
+        Self {
+            size,
+            associativity,
+            line_size,
+        }
+    }
+}
+
+impl fmt::Display for CacheGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.size, self.associativity, self.line_size)
+    }
+}
+
+/// Parses a cache geometry in the format accepted by valgrind: `<size>,<associativity>,<line size>`.
+impl FromStr for CacheGeometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [size, associativity, line_size] = parse_list(s).ok_or_else(|| {
+            format!("invalid cache geometry '{s}' (expected SIZE,ASSOCIATIVITY,LINE_SIZE)")
+        })?;
+        Ok(Self::new(size, associativity, line_size))
+    }
+}
+
+/// Weights used to estimate the number of CPU cycles spent by a benchmark.
+///
+/// The estimate is the sum of the memory accesses that hit the L1 caches, the last-level cache
+/// and RAM, each multiplied by the corresponding weight. If branch simulation is enabled with
+/// [`Config::branch_sim()`], mispredicted branches can also be taken into account, by setting a
+/// misprediction penalty with [`branch_miss()`](Self::branch_miss).
+///
+/// # Examples
+///
+/// ```
+/// use iai::Config;
+/// use iai::CycleModel;
+///
+/// // A small microcontroller, with slow flash memory and no last-level cache to speak of
+/// let config = Config::new().cycle_model(CycleModel::new(1, 1, 20));
+/// # let _ = config;
+///
+/// // The default model, with a penalty of 15 cycles for every mispredicted branch
+/// let config = Config::new()
+///     .branch_sim(true)
+///     .cycle_model(CycleModel::DEFAULT.branch_miss(15));
+/// # let _ = config;
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CycleModel {
+    /// Cycles spent for every access that hits the L1 caches.
+    pub l1_hit: u64,
+    /// Cycles spent for every access that misses the L1 caches, but hits the last-level cache.
+    pub ll_hit: u64,
+    /// Cycles spent for every access that misses all the caches.
+    pub ram_hit: u64,
+    /// Cycles spent for every mispredicted branch. Only used if branch simulation is enabled.
+    pub branch_miss: u64,
+}
+
+impl CycleModel {
+    /// The default model, using Itamar Turner-Trauring's formula from
+    /// <https://pythonspeed.com/articles/consistent-benchmarking-in-ci/>: 1 cycle for L1 hits, 5
+    /// for last-level cache hits and 35 for RAM accesses. Mispredicted branches are not counted.
+    pub const DEFAULT: Self = Self::new(1, 5, 35);
+
+    /// Returns a new cycle model, where mispredicted branches are not counted.
+    pub const fn new(l1_hit: u64, ll_hit: u64, ram_hit: u64) -> Self {
+        Self {
+            l1_hit,
+            ll_hit,
+            ram_hit,
+            branch_miss: 0,
+        }
+    }
+
+    /// Returns a copy of this model, with the given penalty for mispredicted branches.
+    pub const fn branch_miss(mut self, branch_miss: u64) -> Self {
+        self.branch_miss = branch_miss;
+        self
+    }
+}
+
+impl Default for CycleModel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for CycleModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.l1_hit, self.ll_hit, self.ram_hit, self.branch_miss
+        )
+    }
+}
+
+/// Parses a cycle model in the format `<L1 hit>,<LL hit>,<RAM hit>[,<branch miss>]`.
+impl FromStr for CycleModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some([l1_hit, ll_hit, ram_hit]) = parse_list(s) {
+            return Ok(Self::new(l1_hit, ll_hit, ram_hit));
+        }
+        let [l1_hit, ll_hit, ram_hit, branch_miss] = parse_list(s).ok_or_else(|| {
+            format!("invalid cycle model '{s}' (expected L1_HIT,LL_HIT,RAM_HIT[,BRANCH_MISS])")
+        })?;
+        Ok(Self::new(l1_hit, ll_hit, ram_hit).branch_miss(branch_miss))
+    }
+}
+
+/// Parses exactly `N` comma-separated integers.
+fn parse_list<const N: usize>(s: &str) -> Option<[u64; N]> {
+    let values = s
+        .split(',')
+        .map(|value| value.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    values.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use super::MeasurementConfig;
+
+    #[test]
+    fn measurement_config_round_trip() {
+        let config = Config::new()
+            .branch_sim(true)
+            .env("RUST_LOG", "off")
+            .env("MULTILINE", "a=1\nb=\"2\"")
+            .measurement();
+        let stored = config.to_string();
+        assert_eq!(stored.parse::<MeasurementConfig>(), Ok(config.clone()));
+
+        let command = MeasurementConfig {
+            command: Some(0x0123_4567_89ab_cdef),
+            ..config
+        };
+        let stored = command.to_string();
+        assert!(stored.ends_with("command=0123456789abcdef\n"));
+        assert_eq!(stored.parse::<MeasurementConfig>(), Ok(command));
+    }
+
+    #[test]
+    fn measurement_config_without_envs() {
+        // Configurations stored by previous versions of Iai have no environment variables
+        let stored = "i1-cache=32768,8,64\nd1-cache=32768,8,64\nll-cache=8388608,16,64\n\
+                      cycle-model=1,5,35,0\nbranch-sim=no\ntrace-children=no\n";
+        let config = stored.parse::<MeasurementConfig>().unwrap();
+        assert!(config.envs.is_empty());
+        assert_eq!(config.command, None);
+        assert_ne!(config, Config::new().env("RUST_LOG", "off").measurement());
+    }
+}
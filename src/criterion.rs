@@ -0,0 +1,503 @@
+//! Adapter for benchmarks written against the API of [Criterion.rs].
+//!
+//! This module provides types with the same names and methods as the ones from Criterion.rs, so
+//! that the same benchmarks can be run by both harnesses by only changing the imports. It's
+//! available when the `criterion` feature is enabled.
+//!
+//! Every benchmark defined with [`Criterion::bench_function()`],
+//! [`Criterion::bench_with_input()`] or the corresponding methods of [`BenchmarkGroup`] is
+//! reported as a separate Iai benchmark, named after the ID used by Criterion.rs. Since the names
+//! of the benchmarks are also used in the paths of their results, every part of the ID (the group
+//! name, the function name and the parameter) has the characters other than letters, digits, `_`,
+//! `-` and `.` replaced by `_`, and the parts are separated by `::` instead of `/`. The closures
+//! passed to the [`Bencher`] methods are run and measured exactly once. The options that control
+//! sampling and reporting in Criterion.rs are accepted, but have no effect.
+//!
+//! # Examples
+//!
+//! ```
+//! use iai::criterion::BatchSize;
+//! use iai::criterion::BenchmarkId;
+//! use iai::criterion::Criterion;
+//! use iai::criterion::black_box;
+//! use iai::criterion::criterion_group;
+//! use iai::criterion::criterion_main;
+//!
+//! fn fibonacci(n: u64) -> u64 {
+//!     match n {
+//!         0 | 1 => 1,
+//!         n => fibonacci(n - 1) + fibonacci(n - 2),
+//!     }
+//! }
+//!
+//! # #[allow(dead_code)]
+//! fn bench_fibonacci(c: &mut Criterion) {
+//!     c.bench_function("fib 20", |b| b.iter(|| fibonacci(black_box(20))));
+//!
+//!     let mut group = c.benchmark_group("fib");
+//!     for n in [5, 10, 15] {
+//!         group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, &n| {
+//!             b.iter(|| fibonacci(black_box(n)))
+//!         });
+//!     }
+//!     group.finish();
+//! }
+//!
+//! # #[allow(dead_code)]
+//! fn bench_sort(c: &mut Criterion) {
+//!     c.bench_function("sort", |b| {
+//!         b.iter_batched(
+//!             || (0..1000).rev().collect::<Vec<u64>>(),
+//!             |mut vec| {
+//!                 vec.sort();
+//!                 vec
+//!             },
+//!             BatchSize::SmallInput,
+//!         )
+//!     });
+//! }
+//!
+//! criterion_group!(benches, bench_fibonacci, bench_sort);
+//! criterion_main!(benches);
+//! ```
+//!
+//! The benchmarks above are reported as `fib_20`, `fib::5`, `fib::10`, `fib::15` and `sort`.
+//!
+//! [Criterion.rs]: https://docs.rs/criterion
+
+use crate::Iai;
+use crate::registry::path_component;
+use std::fmt;
+use std::time::Duration;
+
+pub use crate::criterion_group;
+pub use crate::criterion_main;
+pub use std::hint::black_box;
+
+/// Collects and runs benchmarks defined with the API of Criterion.rs.
+///
+/// Functions taking a `&mut Criterion` are listed in [`criterion_group!`], or directly in
+/// [`iai::main!`](crate::main!) and [`iai::group!`](crate::group!). Each function is called once
+/// to find the benchmarks that it defines, and then once for every benchmark, which is run only
+/// when its ID matches.
+#[derive(Debug, Default)]
+pub struct Criterion<'a> {
+    mode: Mode<'a>,
+}
+
+#[derive(Debug)]
+enum Mode<'a> {
+    /// Collect the IDs of the benchmarks, without running them.
+    Collect(Vec<String>),
+    /// Run and measure the benchmark with the given ID, skipping all the others.
+    Run { id: String, iai: &'a mut Iai },
+}
+
+impl Default for Mode<'_> {
+    fn default() -> Self {
+        Self::Collect(Vec::new())
+    }
+}
+
+impl<'a> Criterion<'a> {
+    /// Returns a `Criterion` that runs only the benchmark with the given ID.
+    pub(crate) fn run(id: &str, iai: &'a mut Iai) -> Self {
+        Self {
+            mode: Mode::Run {
+                id: id.to_owned(),
+                iai,
+            },
+        }
+    }
+
+    /// Returns the IDs of the benchmarks defined so far, in the order they were defined.
+    pub(crate) fn into_ids(self) -> Vec<String> {
+        match self.mode {
+            Mode::Collect(ids) => ids,
+            Mode::Run { .. } => Vec::new(),
+        }
+    }
+
+    fn bench<F>(&mut self, id: String, f: F)
+    where
+        F: FnOnce(&mut Bencher<'_>),
+    {
+        match &mut self.mode {
+            Mode::Collect(ids) => ids.push(id),
+            Mode::Run { id: target, iai } if *target == id => f(&mut Bencher { iai }),
+            Mode::Run { .. } => {}
+        }
+    }
+
+    /// Defines a benchmark with the given ID.
+    pub fn bench_function<F>(&mut self, id: &str, mut f: F) -> &mut Self
+    where
+        F: FnMut(&mut Bencher<'_>),
+    {
+        self.bench(benchmark_name([id]), |b| f(b));
+        self
+    }
+
+    /// Defines a benchmark with the given ID, which receives `input`.
+    pub fn bench_with_input<F, I>(&mut self, id: BenchmarkId, input: &I, mut f: F) -> &mut Self
+    where
+        F: FnMut(&mut Bencher<'_>, &I),
+        I: ?Sized,
+    {
+        self.bench(benchmark_name(id.parts()), |b| f(b, input));
+        self
+    }
+
+    /// Returns a group of benchmarks, whose IDs are prefixed by `group_name`.
+    pub fn benchmark_group<S: Into<String>>(&mut self, group_name: S) -> BenchmarkGroup<'_, 'a> {
+        BenchmarkGroup {
+            criterion: self,
+            name: group_name.into(),
+        }
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn sample_size(self, _n: usize) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn warm_up_time(self, _dur: Duration) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn measurement_time(self, _dur: Duration) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn noise_threshold(self, _threshold: f64) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn significance_level(self, _sl: f64) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn confidence_level(self, _cl: f64) -> Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn without_plots(self) -> Self {
+        self
+    }
+}
+
+/// A group of benchmarks, returned by [`Criterion::benchmark_group()`].
+#[derive(Debug)]
+pub struct BenchmarkGroup<'c, 'a> {
+    criterion: &'c mut Criterion<'a>,
+    name: String,
+}
+
+impl BenchmarkGroup<'_, '_> {
+    /// Defines a benchmark in this group.
+    pub fn bench_function<ID, F>(&mut self, id: ID, mut f: F) -> &mut Self
+    where
+        ID: IntoBenchmarkId,
+        F: FnMut(&mut Bencher<'_>),
+    {
+        let id = id.into_benchmark_id();
+        let id = benchmark_name([self.name.as_str()].into_iter().chain(id.parts()));
+        self.criterion.bench(id, |b| f(b));
+        self
+    }
+
+    /// Defines a benchmark in this group, which receives `input`.
+    pub fn bench_with_input<ID, F, I>(&mut self, id: ID, input: &I, mut f: F) -> &mut Self
+    where
+        ID: IntoBenchmarkId,
+        F: FnMut(&mut Bencher<'_>, &I),
+        I: ?Sized,
+    {
+        let id = id.into_benchmark_id();
+        let id = benchmark_name([self.name.as_str()].into_iter().chain(id.parts()));
+        self.criterion.bench(id, |b| f(b, input));
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn sample_size(&mut self, _n: usize) -> &mut Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn warm_up_time(&mut self, _dur: Duration) -> &mut Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn measurement_time(&mut self, _dur: Duration) -> &mut Self {
+        self
+    }
+
+    /// Has no effect. Provided for compatibility with Criterion.rs.
+    pub fn throughput(&mut self, _throughput: Throughput) -> &mut Self {
+        self
+    }
+
+    /// Ends the group.
+    pub fn finish(self) {}
+}
+
+/// Runs and measures the code of a benchmark.
+///
+/// Unlike Criterion.rs, which calls the routines many times, every routine is called and
+/// measured once. If a benchmark calls more than one method of `Bencher`, all the routines are
+/// measured and their costs are added together.
+#[derive(Debug)]
+pub struct Bencher<'a> {
+    iai: &'a mut Iai,
+}
+
+impl Bencher<'_> {
+    /// Runs and measures `routine`. The value it returns is dropped outside of the measured
+    /// region.
+    pub fn iter<O, R>(&mut self, routine: R)
+    where
+        R: FnMut() -> O,
+    {
+        drop(self.iai.run(routine));
+    }
+
+    /// Same as [`iter()`](Self::iter), since the value returned by `routine` is always dropped
+    /// outside of the measured region.
+    pub fn iter_with_large_drop<O, R>(&mut self, routine: R)
+    where
+        R: FnMut() -> O,
+    {
+        self.iter(routine)
+    }
+
+    /// Runs `setup`, then runs and measures `routine` with the value returned by `setup`.
+    ///
+    /// Since `routine` takes ownership of its input, the input is dropped inside the measured
+    /// region unless `routine` returns it. Use [`iter_batched_ref()`](Self::iter_batched_ref) to
+    /// drop it outside. The batch size has no effect, since `routine` is run once.
+    pub fn iter_batched<I, O, S, R>(&mut self, setup: S, routine: R, _size: BatchSize)
+    where
+        S: FnMut() -> I,
+        R: FnMut(I) -> O,
+    {
+        self.iai.run_with_setup(setup, routine, drop);
+    }
+
+    /// Runs `setup`, then runs and measures `routine` with a reference to the value returned by
+    /// `setup`. The value is dropped outside of the measured region.
+    ///
+    /// The batch size has no effect, since `routine` is run once.
+    pub fn iter_batched_ref<I, O, S, R>(&mut self, setup: S, routine: R, _size: BatchSize)
+    where
+        S: FnMut() -> I,
+        R: FnMut(&mut I) -> O,
+    {
+        self.iai
+            .run_with_setup_ref(setup, routine, |input, output| drop((input, output)));
+    }
+}
+
+/// ID of a benchmark, made of a function name and a parameter.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BenchmarkId {
+    function_name: Option<String>,
+    parameter: Option<String>,
+}
+
+impl BenchmarkId {
+    /// Returns an ID made of a function name and a parameter, formatted as
+    /// `<function_name>/<parameter>`.
+    pub fn new<S: Into<String>, P: fmt::Display>(function_name: S, parameter: P) -> Self {
+        Self {
+            function_name: Some(function_name.into()),
+            parameter: Some(parameter.to_string()),
+        }
+    }
+
+    /// Returns an ID made only of a parameter. Useful in groups that benchmark a single
+    /// function with different inputs.
+    pub fn from_parameter<P: fmt::Display>(parameter: P) -> Self {
+        Self {
+            function_name: None,
+            parameter: Some(parameter.to_string()),
+        }
+    }
+}
+
+impl BenchmarkId {
+    /// Returns the function name and the parameter, if present.
+    fn parts(&self) -> impl Iterator<Item = &str> {
+        [&self.function_name, &self.parameter]
+            .into_iter()
+            .filter_map(Option::as_deref)
+    }
+}
+
+impl fmt::Display for BenchmarkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.function_name, &self.parameter) {
+            (Some(function_name), Some(parameter)) => write!(f, "{function_name}/{parameter}"),
+            (Some(name), None) | (None, Some(name)) => f.write_str(name),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Separator of the parts of the name of a benchmark. It's not a valid path separator, and it
+/// can't be part of the parts themselves, so that the results of a benchmark named after a group
+/// can't clash with the results of the benchmarks in the group.
+const NAME_SEPARATOR: &str = "::";
+
+/// Returns the name of the benchmark with the given ID, made of the group name (if any), the
+/// function name and the parameter, which is also used in the paths of its results.
+fn benchmark_name<'a, I: IntoIterator<Item = &'a str>>(parts: I) -> String {
+    let name = parts
+        .into_iter()
+        .map(|part| path_component(part.chars()))
+        .collect::<Vec<_>>();
+    if name.is_empty() {
+        path_component("".chars())
+    } else {
+        name.join(NAME_SEPARATOR)
+    }
+}
+
+/// Types that can be used as the ID of a benchmark in a [`BenchmarkGroup`].
+pub trait IntoBenchmarkId {
+    /// Converts this value into a benchmark ID.
+    fn into_benchmark_id(self) -> BenchmarkId;
+}
+
+impl IntoBenchmarkId for BenchmarkId {
+    fn into_benchmark_id(self) -> BenchmarkId {
+        self
+    }
+}
+
+impl<S: Into<String>> IntoBenchmarkId for S {
+    fn into_benchmark_id(self) -> BenchmarkId {
+        BenchmarkId {
+            function_name: Some(self.into()),
+            parameter: None,
+        }
+    }
+}
+
+/// Number of iterations per batch in Criterion.rs. Has no effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BatchSize {
+    SmallInput,
+    LargeInput,
+    PerIteration,
+    NumBatches(u64),
+    NumIterations(u64),
+}
+
+/// Amount of data processed by a benchmark, used by Criterion.rs to report the throughput. Has no
+/// effect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Throughput {
+    Bytes(u64),
+    BytesDecimal(u64),
+    Elements(u64),
+}
+
+/// Macro which defines a group of benchmarks written against the API of Criterion.rs.
+///
+/// Accepts the same syntax as the `criterion_group!` macro from Criterion.rs. The group can be
+/// listed in [`criterion_main!`] or in [`iai::main!`](crate::main!). See the
+/// [`criterion`](crate::criterion) module for an example.
+#[macro_export]
+macro_rules! criterion_group {
+    (
+        name = $name:ident ;
+        config = $config:expr ;
+        targets = $( $target:path ),+ $(,)?
+    ) => {
+        pub fn $name() -> $crate::__private::Group {
+            // The options of Criterion.rs only affect sampling and reporting, which do not apply
+            // to Iai
+            let _: $crate::criterion::Criterion<'_> = $config;
+            let mut group = $crate::__private::Group::new(
+                ::std::option::Option::None,
+                $crate::Config::new(),
+            );
+            $( group.register(stringify!($target), $target); )+
+            group
+        }
+    };
+    ( $name:ident, $( $target:path ),+ $(,)? ) => {
+        $crate::criterion_group!(
+            name = $name;
+            config = $crate::criterion::Criterion::default();
+            targets = $( $target ),+
+        );
+    };
+}
+
+/// Macro which expands to a benchmark harness running the groups defined with
+/// [`criterion_group!`].
+///
+/// Equivalent to [`iai::main!`](crate::main!).
+#[macro_export]
+macro_rules! criterion_main {
+    ( $( $group:ident ),+ $(,)? ) => {
+        $crate::main!($( $group ),+);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BenchmarkId;
+    use super::Criterion;
+
+    fn ids<F: FnOnce(&mut Criterion<'_>)>(f: F) -> Vec<String> {
+        let mut criterion = Criterion::default();
+        f(&mut criterion);
+        criterion.into_ids()
+    }
+
+    #[test]
+    fn ids_cannot_escape_the_results_directory() {
+        let ids = ids(|c| {
+            c.bench_function("..", |_| {});
+            c.bench_function("../../escape", |_| {});
+            c.bench_function("/leading/and/trailing/", |_| {});
+            c.bench_with_input(BenchmarkId::new("..", "."), &(), |_, _| {});
+            let mut group = c.benchmark_group("../group");
+            group.bench_function("/", |_| {});
+            group.finish();
+        });
+        assert_eq!(
+            ids,
+            [
+                "__",
+                ".._.._escape",
+                "leading_and_trailing",
+                "__::_",
+                ".._group::_",
+            ]
+        );
+        assert!(ids.iter().all(|id| !id.contains('/')));
+    }
+
+    #[test]
+    fn grouped_ids_do_not_clash_with_plain_ids() {
+        let ids = ids(|c| {
+            c.bench_function("fib", |_| {});
+            let mut group = c.benchmark_group("fib");
+            group.bench_with_input(BenchmarkId::from_parameter(5), &5, |_, _| {});
+            group.bench_function(BenchmarkId::new("fast", 10), |_| {});
+            group.finish();
+        });
+        assert_eq!(ids, ["fib", "fib::5", "fib::fast::10"]);
+    }
+}
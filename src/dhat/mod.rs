@@ -0,0 +1,6 @@
+mod parser;
+mod stats;
+
+pub(crate) use parser::ParseError;
+pub(crate) use parser::parse_dhat_output;
+pub(crate) use stats::DhatStats;
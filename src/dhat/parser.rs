@@ -0,0 +1,161 @@
+use crate::dhat::DhatStats;
+use crate::json;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Parses a DHAT output file, returning the heap statistics of the whole program.
+///
+/// The output is a JSON document, whose format is described in `dhat/dh_main.c` in the valgrind
+/// sources.
+pub(crate) fn parse_dhat_output<P: AsRef<Path>>(file: P) -> Result<DhatStats, ParseError> {
+    let contents = fs::read_to_string(file).map_err(ParseError::ReadError)?;
+    parse_dhat(&contents)
+}
+
+/// Parses the contents of a DHAT output file.
+pub(crate) fn parse_dhat(contents: &str) -> Result<DhatStats, ParseError> {
+    let output = contents
+        .parse::<json::Value>()
+        .map_err(ParseError::InvalidJson)?;
+
+    if output.get("mode").and_then(json::Value::as_str) != Some("heap") {
+        return Err(ParseError::NotHeapMode);
+    }
+    let program_points = output
+        .get("pps")
+        .and_then(json::Value::as_array)
+        .ok_or(ParseError::MissingField("pps"))?;
+
+    // Every program point is an allocation site: `tbk` and `tb` are the blocks and bytes
+    // allocated there over the whole run, and `gb` the bytes that were still live when the heap
+    // reached its peak size
+    let mut stats = DhatStats {
+        allocations: 0,
+        allocated_bytes: 0,
+        peak_live_bytes: 0,
+    };
+    for program_point in program_points {
+        let get = |key| {
+            program_point
+                .get(key)
+                .and_then(json::Value::as_u64)
+                .ok_or(ParseError::MissingField(key))
+        };
+        stats.allocations += get("tbk")?;
+        stats.allocated_bytes += get("tb")?;
+        stats.peak_live_bytes += get("gb")?;
+    }
+    Ok(stats)
+}
+
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    ReadError(io::Error),
+    InvalidJson(String),
+    NotHeapMode,
+    MissingField(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadError(err) => write!(f, "Failed to read output file: {err}"),
+            Self::InvalidJson(message) => write!(f, "Invalid JSON: {message}"),
+            Self::NotHeapMode => write!(f, "Not the output of DHAT in heap mode"),
+            Self::MissingField(field) => write!(f, "Missing field '{field}'"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseError;
+    use super::parse_dhat;
+    use crate::dhat::DhatStats;
+
+    const SAMPLE: &str = r#"{
+  "dhatFileVersion": 2,
+  "mode": "heap",
+  "verb": "Allocated",
+  "bklt": true,
+  "bkacc": true,
+  "tu": "instrs",
+  "Mtu": "instr",
+  "tuth": 500,
+  "cmd": "bench --iai-run=bench",
+  "pid": 1234,
+  "te": 100000,
+  "tg": 50000,
+  "pps": [
+    {"tb": 1024, "tbk": 1, "tl": 300, "mb": 1024, "mbk": 1, "gb": 1024, "gbk": 1,
+     "eb": 0, "ebk": 0, "rb": 10, "wb": 20, "fs": [1, 2]},
+    {"tb": 96, "tbk": 3, "tl": 30, "mb": 64, "mbk": 2, "gb": 0, "gbk": 0,
+     "eb": 0, "ebk": 0, "rb": 0, "wb": 0, "fs": [3]},
+    {"tb": 16, "tbk": 2, "tl": 10, "mb": 16, "mbk": 2, "gb": 16, "gbk": 2,
+     "eb": 16, "ebk": 2, "rb": 0, "wb": 16, "fs": [1, 3]}
+  ],
+  "ftbl": ["[root]", "0x1: alloc (lib.rs:1)", "0x2: main (main.rs:2)", "0x3: f (lib.rs:3)"]
+}"#;
+
+    #[test]
+    fn program_points_are_summed() {
+        let stats = parse_dhat(SAMPLE).unwrap();
+        assert_eq!(
+            stats,
+            DhatStats {
+                allocations: 6,
+                allocated_bytes: 1136,
+                peak_live_bytes: 1040,
+            }
+        );
+    }
+
+    #[test]
+    fn no_program_points() {
+        let stats = parse_dhat(r#"{"mode": "heap", "pps": []}"#).unwrap();
+        assert_eq!(
+            stats,
+            DhatStats {
+                allocations: 0,
+                allocated_bytes: 0,
+                peak_live_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert!(matches!(
+            parse_dhat(r#"{"mode": "heap"}"#),
+            Err(ParseError::MissingField("pps"))
+        ));
+        assert!(matches!(
+            parse_dhat(r#"{"mode": "heap", "pps": [{"tb": 16, "tbk": 1}]}"#),
+            Err(ParseError::MissingField("gb"))
+        ));
+        assert!(matches!(
+            parse_dhat(r#"{"mode": "heap", "pps": [{"tb": -1, "tbk": 1, "gb": 0}]}"#),
+            Err(ParseError::MissingField("tb"))
+        ));
+        assert!(matches!(
+            parse_dhat(r#"{"pps": []}"#),
+            Err(ParseError::NotHeapMode)
+        ));
+        assert!(matches!(
+            parse_dhat(r#"{"mode": "copy", "pps": []}"#),
+            Err(ParseError::NotHeapMode)
+        ));
+    }
+
+    #[test]
+    fn malformed_json() {
+        for contents in ["", "{", r#"{"mode": "heap", "pps": [}"#, "[1, 2,]", "heap"] {
+            assert!(
+                matches!(parse_dhat(contents), Err(ParseError::InvalidJson(_))),
+                "{contents:?}"
+            );
+        }
+    }
+}
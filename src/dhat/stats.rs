@@ -0,0 +1,99 @@
+/// Heap statistics of a program, as measured by DHAT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct DhatStats {
+    /// Number of heap blocks allocated.
+    pub(crate) allocations: u64,
+    /// Total number of bytes allocated on the heap.
+    pub(crate) allocated_bytes: u64,
+    /// Number of bytes live on the heap at its peak size.
+    pub(crate) peak_live_bytes: u64,
+}
+
+impl DhatStats {
+    pub(crate) fn add(&self, other: &DhatStats) -> DhatStats {
+        DhatStats {
+            allocations: self.allocations + other.allocations,
+            allocated_bytes: self.allocated_bytes + other.allocated_bytes,
+            // The peaks of different processes do not necessarily happen at the same time, so
+            // this is an upper bound
+            peak_live_bytes: self.peak_live_bytes + other.peak_live_bytes,
+        }
+    }
+
+    /// Returns the names of the costs lower than the corresponding costs of `calibration`, which
+    /// [`subtract()`](Self::subtract) clamps to zero.
+    pub(crate) fn saturated_by(&self, calibration: &DhatStats) -> Vec<&'static str> {
+        [
+            ("allocations", self.allocations, calibration.allocations),
+            (
+                "allocated_bytes",
+                self.allocated_bytes,
+                calibration.allocated_bytes,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, cost, calibration)| cost < calibration)
+        .map(|(name, _, _)| name)
+        .collect()
+    }
+
+    /// Subtracts the allocations made by the harness, measured by `calibration`.
+    ///
+    /// The peak live bytes are not a total, but the size of the heap at a single point in time:
+    /// the allocations of the harness may or may not be live at that point, so the peak is kept
+    /// as it is.
+    pub(crate) fn subtract(&self, calibration: &DhatStats) -> DhatStats {
+        DhatStats {
+            allocations: self.allocations.saturating_sub(calibration.allocations),
+            allocated_bytes: self
+                .allocated_bytes
+                .saturating_sub(calibration.allocated_bytes),
+            peak_live_bytes: self.peak_live_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DhatStats;
+
+    fn stats(allocations: u64, allocated_bytes: u64, peak_live_bytes: u64) -> DhatStats {
+        DhatStats {
+            allocations,
+            allocated_bytes,
+            peak_live_bytes,
+        }
+    }
+
+    #[test]
+    fn subtract_calibration() {
+        let calibration = stats(2, 100, 80);
+        assert_eq!(
+            stats(5, 1100, 1000).subtract(&calibration),
+            stats(3, 1000, 1000)
+        );
+        assert!(stats(5, 1100, 1000).saturated_by(&calibration).is_empty());
+    }
+
+    #[test]
+    fn peak_live_bytes_are_not_calibrated() {
+        // The peak is kept even when it's lower than the one of the calibration
+        let calibration = stats(2, 100, 80);
+        assert_eq!(stats(2, 100, 10).subtract(&calibration), stats(0, 0, 10));
+        assert!(stats(2, 100, 10).saturated_by(&calibration).is_empty());
+    }
+
+    #[test]
+    fn subtract_saturates() {
+        let calibration = stats(2, 100, 80);
+        assert_eq!(stats(1, 50, 0).subtract(&calibration), stats(0, 0, 0));
+        assert_eq!(
+            stats(1, 50, 0).saturated_by(&calibration),
+            ["allocations", "allocated_bytes"]
+        );
+        assert_eq!(
+            stats(3, 50, 0).saturated_by(&calibration),
+            ["allocated_bytes"]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use crate::cachegrind::ParseError;
+use crate::dhat;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+/// Errors that can occur while running benchmarks.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A program needed to run the benchmarks, such as valgrind or `setarch`, is not installed,
+    /// or not on `$PATH`.
+    ProgramNotFound {
+        /// Name of the program.
+        program: String,
+    },
+    /// A program needed to run the benchmarks could not be started for any other reason than
+    /// not being found (which is reported as [`ProgramNotFound`](Self::ProgramNotFound)), or its
+    /// output could not be read.
+    Spawn {
+        /// Name of the program.
+        program: String,
+        /// Underlying error.
+        source: io::Error,
+    },
+    /// Valgrind exited unsuccessfully.
+    ValgrindFailed {
+        /// Benchmark that was being run, if any.
+        benchmark: Option<String>,
+        /// Exit status of valgrind.
+        status: ExitStatus,
+        /// Last lines of the output of valgrind, which includes the output of the benchmark.
+        output: String,
+        /// File containing the full output of valgrind, if it was saved.
+        log_file: Option<PathBuf>,
+    },
+    /// An output file produced by valgrind could not be parsed.
+    Parse {
+        /// Path of the output file.
+        path: PathBuf,
+        /// Line where the error was found, starting from 1, if known.
+        line: Option<usize>,
+        /// Description of the error.
+        message: String,
+    },
+    /// A benchmark with the given name does not exist.
+    UnknownBenchmark(String),
+    /// More than one benchmark has the given name.
+    DuplicateBenchmark(String),
+    /// The results of a benchmark in the baseline to compare against were obtained with a
+    /// different configuration.
+    BaselineMismatch {
+        /// Name of the benchmark.
+        benchmark: String,
+        /// Name of the baseline.
+        baseline: String,
+        /// Path of the configuration stored in the baseline.
+        path: PathBuf,
+    },
+    /// A file or directory could not be read or written.
+    Io {
+        /// Path of the file or directory.
+        path: PathBuf,
+        /// Underlying error.
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub(crate) fn spawn(program: &str, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            Self::ProgramNotFound {
+                program: program.to_owned(),
+            }
+        } else {
+            Self::Spawn {
+                program: program.to_owned(),
+                source,
+            }
+        }
+    }
+
+    pub(crate) fn io<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        Self::Io {
+            path: path.as_ref().to_owned(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse<P: AsRef<Path>>(path: P, err: ParseError) -> Self {
+        let path = path.as_ref().to_owned();
+        match err {
+            ParseError::OpenError(source) | ParseError::ReadError(source) => {
+                Self::Io { path, source }
+            }
+            ParseError::InvalidLine(line) => Self::Parse {
+                path,
+                line: Some(line),
+                message: err.to_string(),
+            },
+            err => Self::Parse {
+                path,
+                line: None,
+                message: err.to_string(),
+            },
+        }
+    }
+
+    pub(crate) fn parse_dhat<P: AsRef<Path>>(path: P, err: dhat::ParseError) -> Self {
+        let path = path.as_ref().to_owned();
+        match err {
+            dhat::ParseError::ReadError(source) => Self::Io { path, source },
+            err => Self::Parse {
+                path,
+                line: None,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ProgramNotFound { program } => write!(
+                f,
+                "Failed to run {program}: program not found\n\
+                 Please ensure that {program} is installed and on $PATH"
+            ),
+            Self::Spawn { program, source } => write!(f, "Failed to run {program}: {source}"),
+            Self::ValgrindFailed {
+                benchmark,
+                status,
+                output,
+                log_file,
+            } => {
+                if let Some(benchmark) = benchmark {
+                    write!(f, "Benchmark {benchmark} failed: ")?;
+                }
+                write!(f, "valgrind exited with {status}")?;
+                match output.trim() {
+                    "" => {}
+                    output => write!(f, ":\n{output}")?,
+                }
+                match log_file {
+                    Some(log_file) => write!(f, "\nFull output saved to {}", log_file.display()),
+                    None => Ok(()),
+                }
+            }
+            Self::Parse {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "Failed to parse {}:{line}: {message}", path.display()),
+            Self::Parse {
+                path,
+                line: None,
+                message,
+            } => write!(f, "Failed to parse {}: {message}", path.display()),
+            Self::UnknownBenchmark(name) => write!(f, "No benchmark function with name: {name}"),
+            Self::DuplicateBenchmark(name) => {
+                write!(f, "More than one benchmark with name: {name}")
+            }
+            Self::BaselineMismatch {
+                benchmark,
+                baseline,
+                path,
+            } => write!(
+                f,
+                "Benchmark {benchmark} was measured with a different configuration in baseline \
+                 '{baseline}' (see {})",
+                path.display()
+            ),
+            Self::Io { path, source } => write!(f, "{}: {source}", path.display()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Spawn { source, .. } | Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! History of the results of every run, stored as one JSON object per benchmark and run in
+//! `target/iai/history.jsonl`.
+
+use crate::error::Error;
+use crate::json;
+use crate::metric::Metric;
+use crate::registry::BenchmarkId;
+use crate::report;
+use crate::report::percentage_change;
+use crate::results::Stats;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::SystemTime;
+
+/// Number of largest changes between consecutive runs shown for every metric.
+const LARGEST_JUMPS: usize = 3;
+
+/// Results of the current run, to be appended to the history file.
+#[derive(Clone, Debug)]
+pub(crate) struct History {
+    file: PathBuf,
+    /// Seconds since the Unix epoch, shared by all the results of the run.
+    timestamp: u64,
+    commit: Option<String>,
+    label: Option<String>,
+    records: Vec<json::Value>,
+}
+
+impl History {
+    /// Returns an empty history for the current run, which is stored in `file` together with
+    /// the current git commit, if any, and the given label.
+    pub(crate) fn new(file: PathBuf, label: Option<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        Self {
+            file,
+            timestamp,
+            commit: git_commit(),
+            label,
+            records: Vec::new(),
+        }
+    }
+
+    /// Adds the results of a benchmark, or of a region of a benchmark.
+    pub(crate) fn push(&mut self, benchmark: BenchmarkId<'_>, region: Option<&str>, stats: &Stats) {
+        let cycle_model = &stats.config.cycle_model;
+        let metrics = Metric::ALL
+            .iter()
+            .copied()
+            .filter(|metric| metric.is_measured(stats.tool, &stats.config))
+            .map(|metric| (metric.name(), metric.value(&stats.new, cycle_model).into()));
+        self.records.push(json::Value::object([
+            ("id", benchmark.region_id(region).into()),
+            ("timestamp", self.timestamp.into()),
+            ("commit", self.commit.as_deref().into()),
+            ("label", self.label.as_deref().into()),
+            ("tool", stats.tool.name().into()),
+            ("config", report::json_config(&stats.config)),
+            ("metrics", json::Value::object(metrics)),
+            ("stats", report::json_costs(&stats.new, cycle_model)),
+        ]));
+    }
+
+    /// Appends the results added so far to the history file.
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let lines = self
+            .records
+            .iter()
+            .map(|record| format!("{record}\n"))
+            .collect::<String>();
+        // The results are written at once, so that runs interrupted halfway leave no partial
+        // lines behind
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .map_err(|err| Error::io(&self.file, err))
+    }
+}
+
+/// Returns the commit checked out in the current directory, if it's in a git repository.
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?;
+    let commit = commit.trim();
+    (output.status.success() && !commit.is_empty()).then(|| commit.to_owned())
+}
+
+/// A run of a benchmark, as stored in the history file.
+struct Run {
+    timestamp: u64,
+    commit: Option<String>,
+    label: Option<String>,
+    /// Tool and configuration used. Results obtained with a different configuration are not
+    /// compared with each other.
+    config: (String, json::Value),
+    metrics: Vec<(Metric, u64)>,
+}
+
+impl Run {
+    fn parse(record: &json::Value) -> Option<(String, Self)> {
+        let string = |key| record.get(key).and_then(json::Value::as_str);
+        let metrics = match record.get("metrics")? {
+            json::Value::Object(metrics) => metrics
+                .iter()
+                .filter_map(|(name, value)| Some((name.parse().ok()?, value.as_u64()?)))
+                .collect(),
+            _ => return None,
+        };
+        let run = Self {
+            timestamp: record.get("timestamp")?.as_u64()?,
+            commit: string("commit").map(str::to_owned),
+            label: string("label").map(str::to_owned),
+            config: (string("tool")?.to_owned(), record.get("config")?.clone()),
+            metrics,
+        };
+        Some((string("id")?.to_owned(), run))
+    }
+
+    fn value(&self, metric: Metric) -> Option<u64> {
+        self.metrics
+            .iter()
+            .find(|(m, _)| *m == metric)
+            .map(|(_, value)| *value)
+    }
+
+    /// Describes the run, by its date and, if known, its label and commit.
+    fn describe(&self) -> String {
+        let mut description = format_timestamp(self.timestamp);
+        if let Some(label) = &self.label {
+            description.push_str(&format!(", {label}"));
+        }
+        if let Some(commit) = &self.commit {
+            description.push_str(&format!(", {}", commit.get(..10).unwrap_or(commit)));
+        }
+        description
+    }
+}
+
+/// Prints the trends of the benchmarks selected by `selects` recorded in the history file: for
+/// every metric, the minimum and maximum values, the last `last_runs` values and the largest
+/// changes between consecutive runs.
+pub(crate) fn print_trends(
+    file: &Path,
+    selects: impl Fn(&str) -> bool,
+    last_runs: usize,
+) -> Result<(), Error> {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            println!("No results recorded in {}", file.display());
+            return Ok(());
+        }
+        Err(err) => return Err(Error::io(file, err)),
+    };
+
+    // Benchmarks are reported in the order they first appear in the history
+    let mut benchmarks: Vec<(String, Vec<Run>)> = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, run) = line
+            .parse::<json::Value>()
+            .ok()
+            .as_ref()
+            .and_then(Run::parse)
+            .ok_or_else(|| Error::Parse {
+                path: file.to_owned(),
+                line: Some(line_number + 1),
+                message: "Invalid history record".to_owned(),
+            })?;
+        if !selects(&id) {
+            continue;
+        }
+        match benchmarks.iter_mut().find(|(name, _)| *name == id) {
+            Some((_, runs)) => runs.push(run),
+            None => benchmarks.push((id, vec![run])),
+        }
+    }
+
+    for (id, runs) in &benchmarks {
+        print_benchmark_trends(id, runs, last_runs);
+    }
+    Ok(())
+}
+
+fn print_benchmark_trends(id: &str, runs: &[Run], last_runs: usize) {
+    let last = runs.last().expect("benchmark without runs");
+    println!("{id} ({} runs, last on {})", runs.len(), last.describe());
+
+    for &metric in Metric::ALL {
+        let values = runs
+            .iter()
+            .filter_map(|run| Some((run, run.value(metric)?)))
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            continue;
+        }
+        let min = values.iter().map(|(_, value)| *value).min().unwrap_or(0);
+        let max = values.iter().map(|(_, value)| *value).max().unwrap_or(0);
+        let recent = values[values.len().saturating_sub(last_runs)..]
+            .iter()
+            .map(|(_, value)| value.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        println!("  {}:", metric.label());
+        println!("    {:<16}{min:>15}", "Min:");
+        println!("    {:<16}{max:>15}", "Max:");
+        println!("    {:<16}{recent}", "Last runs:");
+        for (i, (change, run)) in largest_jumps(&values).into_iter().enumerate() {
+            let header = if i == 0 { "Largest jumps:" } else { "" };
+            println!("    {header:<16}{change:>+14.2}%  ({})", run.describe());
+        }
+    }
+    println!();
+}
+
+/// Returns the largest changes between the consecutive values of a metric, as percentages, with
+/// the run where they happened. Runs with a different tool or configuration than the previous run
+/// are not compared with it.
+fn largest_jumps<'a>(values: &[(&'a Run, u64)]) -> Vec<(f64, &'a Run)> {
+    let mut jumps = values
+        .windows(2)
+        .filter(|pair| pair[0].0.config == pair[1].0.config && pair[0].1 != pair[1].1)
+        .filter_map(|pair| Some((percentage_change(pair[1].1, pair[0].1)?, pair[1].0)))
+        .collect::<Vec<_>>();
+    jumps.sort_by(|a, b| b.0.abs().total_cmp(&a.0.abs()));
+    jumps.truncate(LARGEST_JUMPS);
+    jumps
+}
+
+/// Formats a Unix timestamp as a UTC date and time, like `2024-01-31 12:00 UTC`.
+fn format_timestamp(timestamp: u64) -> String {
+    // Converts the number of days since the epoch to a civil date, as described in
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = timestamp / 86400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    let seconds = timestamp % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Returns the path of the history file in the given iai directory.
+pub(crate) fn history_file(iai_dir: &Path) -> PathBuf {
+    iai_dir.join("history.jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use super::Run;
+    use super::format_timestamp;
+    use super::largest_jumps;
+    use crate::Config;
+    use crate::Metric;
+    use crate::Tool;
+    use crate::cachegrind::CachegrindStats;
+    use crate::json;
+    use crate::registry::BenchmarkId;
+    use crate::results::Costs;
+    use crate::results::Stats;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn stats(config: &Config, instructions: u64) -> Stats {
+        Stats {
+            new: Costs::Cachegrind(CachegrindStats::from_events(&["Ir"], &[instructions])),
+            old: None,
+            tool: Tool::Cachegrind,
+            config: config.measurement(),
+            regions: Vec::new(),
+            functions: Vec::new(),
+            old_functions: Vec::new(),
+            parts: Vec::new(),
+        }
+    }
+
+    fn run(config: &str, instructions: u64) -> Run {
+        Run {
+            timestamp: instructions,
+            commit: None,
+            label: None,
+            config: ("cachegrind".to_owned(), json::Value::from(config)),
+            metrics: vec![(Metric::Instructions, instructions)],
+        }
+    }
+
+    #[test]
+    fn saved_runs_are_parsed() {
+        let file = env::temp_dir().join(format!("iai-test-{}-history.jsonl", process::id()));
+        let _ = fs::remove_file(&file);
+        let id = BenchmarkId {
+            group: Some("group"),
+            name: "fib",
+        };
+
+        let mut history = History {
+            file: file.clone(),
+            timestamp: 1_706_702_400,
+            commit: Some("0123456789abcdef".to_owned()),
+            label: Some("before \"fix\"".to_owned()),
+            records: Vec::new(),
+        };
+        history.push(id, None, &stats(&Config::new(), 100));
+        history.push(
+            id,
+            Some("sort"),
+            &stats(&Config::new().branch_sim(true), 50),
+        );
+        history.save().unwrap();
+        // Runs are appended to the file
+        let mut history = History {
+            file: file.clone(),
+            timestamp: 1_706_702_460,
+            commit: None,
+            label: None,
+            records: Vec::new(),
+        };
+        history.push(id, None, &stats(&Config::new(), 90));
+        history.save().unwrap();
+
+        let contents = fs::read_to_string(&file).unwrap();
+        let _ = fs::remove_file(&file);
+        let runs = contents
+            .lines()
+            .map(|line| Run::parse(&line.parse().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(runs.len(), 3);
+
+        let (id, run) = &runs[0];
+        assert_eq!(id, "group/fib");
+        assert_eq!(run.timestamp, 1_706_702_400);
+        assert_eq!(run.commit.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(run.label.as_deref(), Some("before \"fix\""));
+        assert_eq!(run.config.0, "cachegrind");
+        assert_eq!(run.value(Metric::Instructions), Some(100));
+        assert_eq!(run.value(Metric::Branches), None);
+        assert_eq!(
+            run.describe(),
+            "2024-01-31 12:00 UTC, before \"fix\", 0123456789"
+        );
+
+        let (id, region) = &runs[1];
+        assert_eq!(id, "group/fib/sort");
+        assert_eq!(region.value(Metric::Instructions), Some(50));
+        assert_eq!(region.value(Metric::Branches), Some(0));
+        assert_ne!(region.config, run.config);
+
+        let (id, run) = &runs[2];
+        assert_eq!(id, "group/fib");
+        assert_eq!((run.commit.as_deref(), run.label.as_deref()), (None, None));
+        assert_eq!(run.value(Metric::Instructions), Some(90));
+        assert_eq!(run.describe(), "2024-01-31 12:01 UTC");
+    }
+
+    #[test]
+    fn invalid_records() {
+        for record in [
+            "{}",
+            r#"{"id": "fib", "timestamp": 0, "tool": "cachegrind", "config": {}}"#,
+            r#"{"id": "fib", "tool": "cachegrind", "config": {}, "metrics": {}}"#,
+            r#"{"id": "fib", "timestamp": 0, "tool": "cachegrind", "config": {}, "metrics": []}"#,
+        ] {
+            assert!(Run::parse(&record.parse().unwrap()).is_none(), "{record}");
+        }
+        // Metrics unknown to this version of Iai are ignored
+        let record = r#"{"id": "fib", "timestamp": 0, "tool": "cachegrind", "config": {},
+                         "metrics": {"instructions": 5, "unknown": 1}}"#;
+        let (_, run) = Run::parse(&record.parse().unwrap()).unwrap();
+        assert_eq!(run.metrics, [(Metric::Instructions, 5)]);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1_704_067_199), "2023-12-31 23:59 UTC");
+        assert_eq!(format_timestamp(1_706_702_400), "2024-01-31 12:00 UTC");
+        // Leap years, including the ones divisible by 100 and 400
+        assert_eq!(format_timestamp(1_709_164_800), "2024-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_709_251_199), "2024-02-29 23:59 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(951_868_800), "2000-03-01 00:00 UTC");
+        assert_eq!(format_timestamp(4_107_456_000), "2100-02-28 00:00 UTC");
+        assert_eq!(format_timestamp(4_107_542_400), "2100-03-01 00:00 UTC");
+    }
+
+    #[test]
+    fn jumps_within_the_same_configuration() {
+        let runs = [
+            run("a", 100),
+            run("a", 110),
+            run("b", 1000),
+            run("b", 900),
+            run("b", 900),
+            run("a", 200),
+            run("a", 100),
+        ];
+        let values = runs
+            .iter()
+            .map(|run| (run, run.value(Metric::Instructions).unwrap()))
+            .collect::<Vec<_>>();
+        let jumps = largest_jumps(&values)
+            .into_iter()
+            .map(|(change, run)| (change.round() as i64, run.timestamp))
+            .collect::<Vec<_>>();
+        // The changes between configurations (100% and more) and the runs without changes are
+        // skipped, and only the 3 largest jumps are kept
+        assert_eq!(jumps, [(-50, 100), (10, 110), (-10, 900)]);
+    }
+}
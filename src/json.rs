@@ -0,0 +1,450 @@
+//! Minimal JSON support, for the machine-readable output formats and for the output of DHAT.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns an object with the given fields, in the given order.
+    pub(crate) fn object<I, K>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = (K, Value)>,
+        K: Into<String>,
+    {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.into(), value))
+                .collect(),
+        )
+    }
+
+    /// Returns the value of the field with the given name, if this is an object that has it.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::UInt(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::UInt(value)
+    }
+}
+
+impl From<f64> for Value {
+    /// Converts a float to a JSON number. Since JSON cannot represent infinities and NaN, these
+    /// are converted to `null`.
+    fn from(value: f64) -> Self {
+        if value.is_finite() {
+            Self::Float(value)
+        } else {
+            Self::Null
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Self::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Self::Null,
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Formats the value as compact JSON, on a single line.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::UInt(value) => write!(f, "{value}"),
+            Self::Float(value) => write!(f, "{value:?}"),
+            Self::String(value) => write_string(f, value),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Parses a JSON document. Non-negative integers are parsed as [`Value::UInt`], and all other
+/// numbers as [`Value::Float`].
+impl FromStr for Value {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(_) => Err(parser.error("trailing characters")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at offset {}", self.pos)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{expected}'"))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, String> {
+        if self.input[self.pos..].starts_with(keyword) {
+            self.pos += keyword.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Value::Null),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('"') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) => Err(self.error("invalid value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+        {
+            self.pos += 1;
+        }
+        let number = &self.input[start..self.pos];
+        if let Ok(value) = number.parse::<u64>() {
+            return Ok(Value::UInt(value));
+        }
+        number
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    string.push(c);
+                }
+                Some(c) => string.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Parses the digits of a `\uXXXX` escape, including the second half of surrogate pairs.
+    /// Unpaired surrogates are replaced by U+FFFD.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let mut code = self.hex4()?;
+        if (0xd800..0xdc00).contains(&code) && self.input[self.pos..].starts_with("\\u") {
+            let start = self.pos;
+            self.pos += 2;
+            let low = self.hex4()?;
+            if (0xdc00..0xe000).contains(&low) {
+                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+            } else {
+                // The second escape is not part of a pair, so it's parsed on its own
+                self.pos = start;
+            }
+        }
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    fn parse(s: &str) -> Value {
+        s.parse()
+            .unwrap_or_else(|err| panic!("failed to parse {s:?}: {err}"))
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            parse(r#""quote \" backslash \\ slash \/ \b\f\n\r\t""#),
+            Value::from("quote \" backslash \\ slash / \u{8}\u{c}\n\r\t")
+        );
+        assert_eq!(parse(r#""\u0041\u00e9\u20AC""#), Value::from("Aé€"));
+        assert_eq!(parse(r#""e\u0301""#), Value::from("e\u{301}"));
+        assert_eq!(parse(r#""héllo →""#), Value::from("héllo →"));
+        assert!(r#""\x""#.parse::<Value>().is_err());
+        assert!(r#""\u12""#.parse::<Value>().is_err());
+        assert!(r#""\u+123""#.parse::<Value>().is_err());
+        assert!(r#""unterminated"#.parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(parse(r#""\ud83d\ude00""#), Value::from("😀"));
+        assert_eq!(parse(r#""\uD834\uDD1E""#), Value::from("𝄞"));
+        // Unpaired surrogates
+        assert_eq!(parse(r#""\ud83d""#), Value::from("\u{fffd}"));
+        assert_eq!(parse(r#""\ude00x""#), Value::from("\u{fffd}x"));
+        assert_eq!(parse(r#""\ud83dA""#), Value::from("\u{fffd}A"));
+        assert_eq!(parse(r#""\ud83d\u0041""#), Value::from("\u{fffd}A"));
+        assert_eq!(parse(r#""\ud83d\ud83d\ude00""#), Value::from("\u{fffd}😀"));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("0"), Value::UInt(0));
+        assert_eq!(parse("9223372036854775808"), Value::UInt(1 << 63));
+        assert_eq!(parse("18446744073709551615"), Value::UInt(u64::MAX));
+        assert_eq!(
+            parse("18446744073709551616"),
+            Value::Float(18_446_744_073_709_551_616.0)
+        );
+        assert_eq!(parse("-1"), Value::Float(-1.0));
+        assert_eq!(parse("1.5e3"), Value::Float(1500.0));
+        assert_eq!(parse("1E-2"), Value::Float(0.01));
+        assert!("1.2.3".parse::<Value>().is_err());
+        assert!("-".parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn nested_objects() {
+        let value =
+            parse(r#" { "a" : { "b" : [ 1 , { "c" : null } , [] ] , "d" : {} } , "e" : true } "#);
+        let a = value.get("a").unwrap();
+        let b = a.get("b").unwrap().as_array().unwrap();
+        assert_eq!(b[0].as_u64(), Some(1));
+        assert_eq!(b[1].get("c"), Some(&Value::Null));
+        assert_eq!(b[2], Value::Array(Vec::new()));
+        assert_eq!(a.get("d"), Some(&Value::Object(Vec::new())));
+        assert_eq!(value.get("e"), Some(&Value::Bool(true)));
+        assert_eq!(value.get("f"), None);
+        assert!(r#"{"a":1,}"#.parse::<Value>().is_err());
+        assert!(r#"{"a" 1}"#.parse::<Value>().is_err());
+        assert!("[1 2]".parse::<Value>().is_err());
+        assert!("{} {}".parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = Value::object([
+            ("string", Value::from("\"quoted\"\n\t\\ \u{1} é 😀")),
+            ("max", Value::from(u64::MAX)),
+            ("float", Value::from(-1.25)),
+            ("large", Value::from(1e300)),
+            ("infinite", Value::from(f64::INFINITY)),
+            ("bool", Value::from(false)),
+            ("none", Value::from(None::<u64>)),
+            (
+                "nested",
+                Value::object([("array", Value::from(vec![Value::from(1_u64), Value::Null]))]),
+            ),
+            ("\"key\"", Value::object::<_, String>([])),
+        ]);
+        let serialized = value.to_string();
+        assert!(!serialized.contains('\n'));
+        assert_eq!(parse(&serialized), value);
+        assert_eq!(value.get("infinite"), Some(&Value::Null));
+    }
+}
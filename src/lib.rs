@@ -5,25 +5,6 @@
 #![warn(unused_crate_dependencies)]
 #![doc(test(attr(deny(warnings))))]
 
-mod cachegrind;
-mod macros;
-
-use crate::cachegrind::Cachegrind;
-use crate::cachegrind::CachegrindStats;
-use crate::cachegrind::parse_cachegrind_output;
-use clap::Parser;
-use std::convert::Infallible;
-use std::env;
-use std::error::Error;
-use std::ffi::OsStr;
-use std::ffi::OsString;
-use std::fs;
-use std::hint::black_box;
-use std::io;
-use std::path::PathBuf;
-use std::process::ExitCode;
-use std::str::FromStr;
-
 macro_rules! warn {
      ( $( $tt:tt )* ) => {{
          ::std::eprint!("warning: ");
@@ -38,137 +19,221 @@ macro_rules! error {
      }}
 }
 
+mod cachegrind;
+mod callgrind;
+mod command;
+mod compare;
+mod config;
+#[cfg(feature = "criterion")]
+pub mod criterion;
+mod dhat;
+mod error;
+mod history;
+mod json;
+mod macros;
+mod metric;
+mod registry;
+mod regression;
+mod report;
+mod results;
+mod runner;
+mod valgrind;
+
+pub use crate::command::Command;
+pub use crate::config::CacheGeometry;
+pub use crate::config::Config;
+pub use crate::config::CycleModel;
+pub use crate::config::Tool;
+pub use crate::error::Error;
+pub use crate::metric::Metric;
+#[cfg(feature = "macros")]
+pub use iai_macros::bench;
+
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "macros")]
+    pub use crate::registry::BENCHMARKS;
+    pub use crate::registry::Group;
+    pub use crate::registry::Register;
+    #[cfg(feature = "macros")]
+    pub use crate::registry::Registration;
+    #[cfg(feature = "macros")]
+    pub use linkme;
+}
+
+use crate::compare::compare_results;
+use crate::registry::BenchmarkFn;
+use crate::registry::Group;
+use crate::registry::UserBenchmark;
+use crate::regression::RegressionThreshold;
+use crate::report::OutputFormat;
+use crate::results::iai_dir;
+use crate::runner::Benchmark;
+use crate::runner::run_all_benchmarks;
+use clap::Parser;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::hint::black_box;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
     #[arg(long)]
     bench: bool,
 
-    #[arg(long)]
-    #[doc(hidden)]
-    iai_run: Option<Benchmark>,
-}
-
-#[derive(Clone, Debug)]
-enum Benchmark {
-    User(String),
-    Calibration,
-}
+    /// Run only the benchmarks whose name contains one of the filters
+    #[arg(value_name = "FILTER")]
+    filters: Vec<String>,
 
-impl Benchmark {
-    fn name(&self) -> &str {
-        match self {
-            Self::User(name) => name,
-            Self::Calibration => "::iai::calibration",
-        }
-    }
-}
+    /// Match the filters exactly, instead of as substrings
+    #[arg(long)]
+    exact: bool,
 
-impl FromStr for Benchmark {
-    type Err = Infallible;
+    /// Skip the benchmarks whose name contains the given filter. Can be repeated
+    #[arg(long, value_name = "FILTER")]
+    skip: Vec<String>,
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == Self::Calibration.name() {
-            Ok(Self::Calibration)
-        } else {
-            Ok(Self::User(s.to_string()))
-        }
-    }
-}
+    /// List the benchmarks instead of running them
+    #[arg(long)]
+    list: bool,
+
+    /// Run only the ignored benchmarks. Iai benchmarks cannot be ignored, so this selects no
+    /// benchmarks; accepted for compatibility with the standard test harness
+    #[arg(long, hide = true)]
+    ignored: bool,
+
+    /// Format of the output of the standard test harness. Accepted for compatibility, but ignored
+    #[arg(long, hide = true)]
+    format: Option<String>,
+
+    /// Options of the standard test harness, passed by `cargo bench` or IDEs when running all the
+    /// targets of a crate. Accepted for compatibility, but ignored
+    #[arg(long, hide = true)]
+    nocapture: bool,
+    #[arg(long, hide = true)]
+    show_output: bool,
+    #[arg(long, hide = true)]
+    test_threads: Option<String>,
+    #[arg(short, long, hide = true)]
+    quiet: bool,
+    #[arg(long, hide = true)]
+    color: Option<String>,
+
+    /// Save the results under a named baseline, in `target/iai/<NAME>`, and compare against the
+    /// results previously saved with the same name
+    #[arg(long, value_name = "NAME", value_parser = parse_baseline_name)]
+    save_baseline: Option<String>,
+
+    /// Compare against a named baseline instead of the previous run. Unless --save-baseline is
+    /// also given, nothing is overwritten: the results are stored next to the baseline, with a
+    /// `.new` extension
+    #[arg(long, value_name = "NAME", value_parser = parse_baseline_name)]
+    baseline: Option<String>,
+
+    /// Fail if a metric increases by more than the given percentage compared to the previous
+    /// run. Can be repeated; if a benchmark name is given, the threshold applies only to that
+    /// benchmark
+    #[arg(
+        long = "regression-threshold",
+        value_name = "[BENCHMARK:]METRIC=PERCENT"
+    )]
+    regression_thresholds: Vec<RegressionThreshold>,
+
+    /// Valgrind tool used for the benchmarks that do not set one in their configuration
+    /// (cachegrind, callgrind or dhat)
+    #[arg(long, value_name = "TOOL")]
+    tool: Option<Tool>,
+
+    /// Number of benchmarks to run concurrently. The results are always reported in the same
+    /// order as the benchmarks are defined
+    #[arg(short, long, value_name = "N", default_value = "1")]
+    jobs: NonZeroUsize,
+
+    /// Format of the results printed on standard output
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    output_format: OutputFormat,
+
+    /// Show the N functions with the highest cost in every benchmark, and the N functions whose
+    /// cost changed the most compared to the previous run. For callgrind, also show the call graph
+    /// of the N functions with the highest inclusive cost
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    top_functions: usize,
+
+    /// Also write the results to PATH as Markdown tables, suitable for pull request comments
+    #[arg(long, value_name = "PATH")]
+    markdown_report: Option<PathBuf>,
+
+    /// Print the trends of the results recorded in `target/iai/history.jsonl` by previous runs,
+    /// instead of running the benchmarks
+    #[arg(long)]
+    history: bool,
 
-#[derive(Clone, Debug)]
-struct Stats {
-    new: CachegrindStats,
-    old: Option<CachegrindStats>,
-}
+    /// Label stored in the history together with the results of this run, in addition to the
+    /// current git commit
+    #[arg(long, value_name = "LABEL")]
+    history_label: Option<String>,
 
-impl Stats {
-    fn subtract(&self, other: &Self) -> Self {
-        let new = self.new.subtract(&other.new);
-        let old = match (&self.old, &other.old) {
-            (Some(a), Some(b)) => Some(a.subtract(b)),
-            _ => None,
-        };
-        Self { new, old }
-    }
-}
+    /// Number of most recent values of every metric shown by --history
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    history_runs: usize,
 
-#[derive(Clone, Debug)]
-struct BenchRunner {
-    executable: OsString,
-    allow_aslr: bool,
-}
+    /// Do not subtract the overhead of the benchmark harness from the results of the benchmarks
+    #[arg(long)]
+    no_calibration: bool,
 
-impl BenchRunner {
-    fn new<S: AsRef<OsStr>>(executable: S) -> Self {
-        Self {
-            executable: executable.as_ref().to_owned(),
-            allow_aslr: false,
-        }
-    }
+    /// Compare the results saved in two directories (such as `target/iai/<NAME>` for a named
+    /// baseline) instead of running the benchmarks. Benchmarks are matched by name, and the
+    /// results in NEW are reported as changes from the ones in OLD
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    compare: Option<Vec<PathBuf>>,
 
-    fn allow_aslr(&mut self, allow_aslr: bool) -> &mut Self {
-        self.allow_aslr = allow_aslr;
-        self
-    }
+    #[arg(long, hide = true)]
+    iai_run: Option<String>,
 
-    fn run(&mut self, benchmark: &Benchmark) -> Result<Stats, Box<dyn Error>> {
-        let name = benchmark.name();
-
-        let target_dir =
-            PathBuf::from(env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into()));
-        let iai_dir = target_dir.join("iai");
-        let output_file = iai_dir.join(format!("cachegrind.out.{}", name));
-        let old_file = iai_dir.join(format!("cachegrind.out.{}.old", name));
-
-        fs::create_dir_all(&iai_dir)
-            .map_err(|err| format!("Failed to create directory {}: {}", iai_dir.display(), err))?;
-
-        // If this benchmark was already run once, move the last results to .old
-        match fs::rename(&output_file, &old_file) {
-            Ok(()) => {}
-            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
-            Err(err) => warn!(
-                "Failed to rename {} to {}: {}",
-                output_file.display(),
-                old_file.display(),
-                err
-            ),
-        }
+    #[arg(long, hide = true, requires = "iai_run")]
+    iai_region: Option<String>,
 
-        Cachegrind::new()
-            .allow_aslr(self.allow_aslr)
-            .out_file(&output_file)
-            .run([&self.executable, &format!("--iai-run={name}").into()])
-            .map_err(|err| format!("Failed to run benchmark {name} in cachegrind: {err}"))?;
+    #[arg(long, hide = true, requires = "iai_run")]
+    iai_regions_file: Option<PathBuf>,
+}
 
-        let new = parse_cachegrind_output(&output_file).map_err(|err| {
-            format!("Failed to parse cachegrind output for benchmark {name}: {err}")
-        })?;
-        let old = parse_cachegrind_output(&old_file).ok();
+impl Args {
+    /// Returns whether the benchmark with the given name is selected by the filters given on
+    /// the command line.
+    fn selects(&self, name: &str) -> bool {
+        let matches = |filter: &String| {
+            if self.exact {
+                name == filter
+            } else {
+                name.contains(filter.as_str())
+            }
+        };
+        !self.ignored
+            && (self.filters.is_empty() || self.filters.iter().any(matches))
+            && !self.skip.iter().any(matches)
+    }
+}
 
-        Ok(Stats { new, old })
+fn parse_baseline_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        Err(format!("invalid baseline name: '{name}'"))
+    } else {
+        Ok(name.to_owned())
     }
 }
 
-type UserBenchmarks = [(&'static str, fn(&'_ mut Iai))];
+type UserBenchmarks = [UserBenchmark];
 
 /// Custom-test-framework runner. Should not be called directly.
 #[must_use]
 #[doc(hidden)]
-pub fn runner(benches: &UserBenchmarks) -> ExitCode {
-    let args = Args::parse();
-
-    let result = if let Some(ref bench) = args.iai_run {
-        // We've been asked to run a single benchmark under valgrind
-        run_benchmark(benches, bench)
-    } else {
-        // Otherwise we're running normally under cargo
-        run_all_benchmarks(benches)
-    };
-
-    match result {
-        Ok(()) => ExitCode::SUCCESS,
+pub fn runner(group: &Group) -> ExitCode {
+    match try_runner(group) {
+        Ok(code) => code,
         Err(err) => {
             error!("{err}");
             ExitCode::FAILURE
@@ -176,147 +241,346 @@ pub fn runner(benches: &UserBenchmarks) -> ExitCode {
     }
 }
 
-fn run_benchmark(benches: &UserBenchmarks, bench: &Benchmark) -> Result<(), Box<dyn Error>> {
-    if !cachegrind::running_on_valgrind() {
-        warn!("Not running under valgrind");
+/// Fallible custom-test-framework runner, used by `iai::main!(name = ...)`. Should not be called
+/// directly.
+#[doc(hidden)]
+pub fn try_runner(group: &Group) -> Result<ExitCode, Error> {
+    let args = Args::parse();
+    let benches = group.benchmarks();
+
+    // Benchmarks with the same name would share their results files
+    let mut ids = HashSet::new();
+    if let Some(benchmark) = benches.iter().find(|benchmark| !ids.insert(benchmark.id())) {
+        return Err(Error::DuplicateBenchmark(benchmark.id()));
     }
 
-    match bench {
-        Benchmark::User(name) => {
-            let f = benches
-                .iter()
-                .find_map(|(name, f)| if *name == bench.name() { Some(f) } else { None })
-                .ok_or_else(|| format!("no benchmark function with name: {name}"))?;
-            let mut iai = Iai::new();
-            f(&mut iai);
-            Ok(())
-        }
-        Benchmark::Calibration => {
-            Iai::new().run(|| {});
-            Ok(())
+    if let Some(ref id) = args.iai_run {
+        // We've been asked to run a single benchmark under valgrind
+        run_benchmark(benches, id, &args).map(|()| ExitCode::SUCCESS)
+    } else if args.list {
+        // Use the same format as the standard test harness, so that IDEs can find the benchmarks
+        for benchmark in benches.iter().filter(|b| args.selects(&b.id())) {
+            println!("{}: bench", benchmark.id());
         }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some([old_dir, new_dir]) = args.compare.as_deref() {
+        compare_results(benches, &args, old_dir, new_dir)
+    } else if args.history {
+        history::print_trends(
+            &history::history_file(&iai_dir()),
+            |id| args.selects(id),
+            args.history_runs,
+        )
+        .map(|()| ExitCode::SUCCESS)
+    } else {
+        // Otherwise we're running normally under cargo
+        run_all_benchmarks(benches, &args)
     }
 }
 
-fn run_all_benchmarks(benches: &UserBenchmarks) -> Result<(), Box<dyn Error>> {
-    let executable = env::args_os().next().expect("first argument is missing");
-
-    Cachegrind::check()
-        .map_err(|err| format!("{err}\nPlease ensure that valgrind is installed and on $PATH"))?;
-
-    let mut runner = BenchRunner::new(executable);
-    runner.allow_aslr(env::var_os("IAI_ALLOW_ASLR").is_some());
+fn run_benchmark(benches: &UserBenchmarks, id: &str, args: &Args) -> Result<(), Error> {
+    if !valgrind::running_on_valgrind() {
+        warn!("Not running under valgrind");
+    }
 
-    let calibration_stats = runner.run(&Benchmark::Calibration)?;
+    // The calibration runs the same code as the benchmarks, except for the benchmark function, so
+    // that it has the same overhead. This includes the heap allocations measured by DHAT, so the
+    // benchmark must be found without allocating
+    let func = if id == Benchmark::Calibration.name() {
+        None
+    } else {
+        let benchmark = benches
+            .iter()
+            .find(|benchmark| benchmark.has_id(id))
+            .ok_or_else(|| Error::UnknownBenchmark(id.to_owned()))?;
+        let BenchmarkFn::Function(func) = &benchmark.func else {
+            return Err(Error::UnknownBenchmark(id.to_owned()));
+        };
+        Some(func)
+    };
 
-    for (name, _func) in benches.iter() {
-        println!("{}", name);
-        let stats = runner.run(&Benchmark::User(name.to_string()))?;
+    let mut iai = Iai::new(args.tool.unwrap_or_default(), args.iai_region.clone());
+    match func {
+        Some(func) => func(&mut iai),
+        None => iai.run(|| {}),
+    }
 
-        let stats = stats.subtract(&calibration_stats);
+    if let Some(regions_file) = &args.iai_regions_file {
+        let regions = iai
+            .regions
+            .iter()
+            .map(|region| format!("{region}\n"))
+            .collect::<String>();
+        fs::write(regions_file, regions).map_err(|err| Error::io(regions_file, err))?;
+    }
+    Ok(())
+}
 
-        fn signed_short(n: f64) -> String {
-            let n_abs = n.abs();
+#[derive(Debug)]
+pub struct Iai {
+    tool: Tool,
+    /// If set, only the region with this name is measured.
+    region: Option<String>,
+    /// Names of the regions run so far, in the order they were first run.
+    regions: Vec<String>,
+}
 
-            if n_abs < 10.0 {
-                format!("{:+.6}", n)
-            } else if n_abs < 100.0 {
-                format!("{:+.5}", n)
-            } else if n_abs < 1000.0 {
-                format!("{:+.4}", n)
-            } else if n_abs < 10000.0 {
-                format!("{:+.3}", n)
-            } else if n_abs < 100000.0 {
-                format!("{:+.2}", n)
-            } else if n_abs < 1000000.0 {
-                format!("{:+.1}", n)
-            } else {
-                format!("{:+.0}", n)
-            }
+impl Iai {
+    fn new(tool: Tool, region: Option<String>) -> Self {
+        Self {
+            tool,
+            region,
+            regions: Vec::new(),
         }
+    }
 
-        fn percentage_diff(new: u64, old: u64) -> String {
-            if new == old {
-                return " (No change)".to_owned();
-            }
-
-            let new: f64 = new as f64;
-            let old: f64 = old as f64;
-
-            let diff = (new - old) / old;
-            let pct = diff * 100.0;
-
-            format!(" ({:>+6}%)", signed_short(pct))
+    /// Runs and measures the given closure.
+    ///
+    /// The result of the closure is returned. This implies that, if the return type implements
+    /// [`Drop`], the overhead of the `Drop` implementation is not measured.
+    pub fn run<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        match self.region {
+            Some(_) => black_box(f()),
+            None => self.measured(f),
         }
+    }
 
-        println!(
-            "  Instructions:     {:>15}{}",
-            stats.new.instruction_reads,
-            match &stats.old {
-                Some(old) => percentage_diff(stats.new.instruction_reads, old.instruction_reads),
-                None => "".to_owned(),
-            }
+    /// Runs and measures the given closure as a named region of the benchmark.
+    ///
+    /// Every region is reported separately, in addition to the whole benchmark (which includes
+    /// all the regions, as well as the closures passed to [`run()`](Self::run)). Regions are
+    /// reported as `<benchmark>/<name>`. If a region with the same name is run more than once,
+    /// the measurements are added together.
+    ///
+    /// With callgrind, all the regions are measured in the same run as the whole benchmark. With
+    /// cachegrind, the benchmark function is run again for every region, measuring only that
+    /// region: benchmark functions that use regions must therefore run the same regions every
+    /// time they are called, and any setup code is run once per region, so callgrind is better
+    /// suited to benchmarks with expensive setup. When threads are measured separately with
+    /// callgrind, a region only includes the costs of the thread that runs it.
+    ///
+    /// ```
+    /// # use iai::Iai;
+    /// # #[allow(dead_code)]
+    /// fn bench_parse_and_sum(iai: &mut Iai) {
+    ///     let input = (0..1000).map(|n| n.to_string()).collect::<Vec<_>>().join(" ");
+    ///     let numbers = iai.measure("parse", || {
+    ///         input
+    ///             .split(' ')
+    ///             .map(|n| n.parse::<u64>().unwrap())
+    ///             .collect::<Vec<_>>()
+    ///     });
+    ///     iai.measure("sum", || numbers.iter().sum::<u64>());
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `name` is empty, `.` or `..`, or contains slashes, line breaks or NUL characters.
+    pub fn measure<F, T>(&mut self, name: &str, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        assert!(
+            !matches!(name, "" | "." | "..") && !name.contains(['/', '\\', '\n', '\r', '\0']),
+            "invalid region name: {name:?}"
         );
+        if self.tool == Tool::Dhat {
+            // DHAT always measures the whole benchmark, so regions cannot be measured separately
+            return black_box(f());
+        }
+        if !self.regions.iter().any(|region| region == name) {
+            self.regions.push(name.to_owned());
+        }
 
-        let summary = stats.new.summarize();
-        let old_summary = stats.old.map(|stat| stat.summarize());
-        println!(
-            "  L1 Accesses:      {:>15}{}",
-            summary.l1_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.l1_hits, old.l1_hits),
-                None => "".to_owned(),
+        match &self.region {
+            Some(region) if region != name => black_box(f()),
+            None if self.tool == Tool::Callgrind => {
+                // The costs measured so far are dumped before the region, so that the costs dumped
+                // at the end of the region only include the region itself
+                let trigger = CString::new(format!("{}{name}", callgrind::REGION_TRIGGER))
+                    .expect("region name contains NUL characters");
+                callgrind::dump_stats();
+                let result = self.measured(f);
+                callgrind::dump_stats_at(&trigger);
+                result
             }
-        );
-        println!(
-            "  L2 Accesses:      {:>15}{}",
-            summary.l3_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.l3_hits, old.l3_hits),
-                None => "".to_owned(),
-            }
-        );
-        println!(
-            "  RAM Accesses:     {:>15}{}",
-            summary.ram_hits,
-            match &old_summary {
-                Some(old) => percentage_diff(summary.ram_hits, old.ram_hits),
-                None => "".to_owned(),
+            _ => self.measured(f),
+        }
+    }
+
+    fn measured<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        match self.tool {
+            Tool::Cachegrind => {
+                cachegrind::start_instrumentation();
+                let result = black_box(f());
+                cachegrind::stop_instrumentation();
+                result
             }
-        );
-        println!(
-            "  Estimated Cycles: {:>15}{}",
-            summary.cycles(),
-            match &old_summary {
-                Some(old) => percentage_diff(summary.cycles(), old.cycles()),
-                None => "".to_owned(),
+            Tool::Callgrind => {
+                callgrind::start_instrumentation();
+                callgrind::toggle_collect();
+                let result = black_box(f());
+                callgrind::toggle_collect();
+                callgrind::stop_instrumentation();
+                result
             }
-        );
-        println!();
+            Tool::Dhat => black_box(f()),
+        }
     }
 
-    Ok(())
-}
-
-#[derive(Debug)]
-pub struct Iai {}
-
-impl Iai {
-    fn new() -> Self {
-        Self {}
+    /// Runs the given `setup` closure, then runs and measures `routine`, and finally runs
+    /// `teardown`.
+    ///
+    /// Only `routine` is measured. The value returned by `setup` is passed by value to `routine`,
+    /// and the value returned by `routine` is passed by value to `teardown`; the result of
+    /// `teardown` is returned.
+    ///
+    /// If `routine` does not consume its input, the input is dropped inside the measured region.
+    /// To exclude the [`Drop`] implementation of the input from the measurements, return the input
+    /// as part of the output of `routine`, so that it is dropped by `teardown`, or use
+    /// [`run_with_setup_ref()`](Self::run_with_setup_ref):
+    ///
+    /// ```
+    /// # use iai::Iai;
+    /// # #[allow(dead_code)]
+    /// fn bench_sort(iai: &mut Iai) {
+    ///     iai.run_with_setup(
+    ///         || (0..1000).rev().collect::<Vec<u64>>(),
+    ///         |mut vec| {
+    ///             vec.sort();
+    ///             vec
+    ///         },
+    ///         |vec| assert!(vec.is_sorted()),
+    ///     );
+    /// }
+    /// ```
+    pub fn run_with_setup<S, I, F, O, T, R>(&mut self, setup: S, routine: F, teardown: T) -> R
+    where
+        S: FnOnce() -> I,
+        F: FnOnce(I) -> O,
+        T: FnOnce(O) -> R,
+    {
+        let input = black_box(setup());
+        let output = self.run(|| routine(input));
+        teardown(output)
     }
 
-    /// Runs and measures the given closure.
+    /// Same as [`run_with_setup()`](Self::run_with_setup), except that `routine` is given a
+    /// mutable reference to the value returned by `setup`.
     ///
-    /// The result of the closure is returned. This implies that, if the return type implements
-    /// [`Drop`], the overhead of the `Drop` implementation is not measured.
-    pub fn run<F, T>(&mut self, f: F) -> T
+    /// The input stays owned by the harness, and is passed by value to `teardown` together with
+    /// the value returned by `routine`, so neither of them is dropped inside the measured region.
+    ///
+    /// ```
+    /// # use iai::Iai;
+    /// # #[allow(dead_code)]
+    /// fn bench_sort(iai: &mut Iai) {
+    ///     iai.run_with_setup_ref(
+    ///         || (0..1000).rev().collect::<Vec<u64>>(),
+    ///         |vec| vec.sort(),
+    ///         |vec, ()| assert!(vec.is_sorted()),
+    ///     );
+    /// }
+    /// ```
+    pub fn run_with_setup_ref<S, I, F, O, T, R>(&mut self, setup: S, routine: F, teardown: T) -> R
     where
-        F: FnOnce() -> T,
+        S: FnOnce() -> I,
+        F: FnOnce(&mut I) -> O,
+        T: FnOnce(I, O) -> R,
     {
-        cachegrind::start_instrumentation();
-        let result = black_box(f());
-        cachegrind::stop_instrumentation();
-        result
+        let mut input = black_box(setup());
+        let output = self.run(|| routine(&mut input));
+        teardown(input, output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use clap::Parser;
+
+    #[test]
+    fn accepts_test_harness_options() {
+        let args = Args::try_parse_from([
+            "bench",
+            "--bench",
+            "--nocapture",
+            "--show-output",
+            "--test-threads",
+            "1",
+            "-q",
+            "--color",
+            "never",
+            "--format",
+            "terse",
+            "fib",
+        ])
+        .unwrap();
+        assert_eq!(args.filters, ["fib"]);
+        assert!(args.selects("fibonacci"));
+        assert!(!args.selects("sort"));
+
+        let args = Args::try_parse_from(["bench", "--quiet", "--test-threads=4", "--color=always"]);
+        assert!(args.unwrap().filters.is_empty());
+    }
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(["bench"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn filters_match_substrings() {
+        let all = parse(&[]);
+        assert!(all.selects("fib") && all.selects("group/sort"));
+
+        let args = parse(&["fib", "group/"]);
+        assert!(args.selects("fib"));
+        assert!(args.selects("fibonacci/10"));
+        assert!(args.selects("group/sort"));
+        assert!(args.selects("other/fib"));
+        assert!(!args.selects("sort"));
+        assert!(!args.selects("Fib"));
+    }
+
+    #[test]
+    fn exact_disables_substring_matching() {
+        let args = parse(&["--exact", "fib", "group/sort"]);
+        assert!(args.selects("fib"));
+        assert!(args.selects("group/sort"));
+        assert!(!args.selects("fibonacci"));
+        assert!(!args.selects("group/fib"));
+        assert!(!args.selects("group/sort/search"));
+    }
+
+    #[test]
+    fn skip_wins_over_filters() {
+        let args = parse(&["fib", "--skip", "long", "--skip", "group/"]);
+        assert!(args.selects("fib"));
+        assert!(args.selects("fib/10"));
+        assert!(!args.selects("fib_long"));
+        assert!(!args.selects("group/fib"));
+        assert!(!args.selects("sort"));
+
+        // Without filters, everything but the skipped benchmarks is selected
+        let args = parse(&["--skip", "long"]);
+        assert!(args.selects("sort"));
+        assert!(!args.selects("fib_long"));
+
+        // With --exact, only the benchmarks with exactly the skipped name are skipped
+        let args = parse(&["--exact", "--skip", "fib"]);
+        assert!(!args.selects("fib"));
+        assert!(args.selects("fib_long"));
+    }
+
+    #[test]
+    fn ignored_selects_nothing() {
+        assert!(!parse(&["--ignored"]).selects("fib"));
     }
 }
@@ -40,14 +40,227 @@
 ///
 /// The `iai::main` macro expands to a `main` function which runs all of the
 /// benchmarks in the given groups.
+///
+/// # Parameterized benchmarks
+///
+/// A benchmark function can also accept an additional input argument. In this
+/// case, the list of inputs must be given with `args = [...]`, and the
+/// function is benchmarked once for every input:
+///
+/// ```
+/// use iai::Iai;
+///
+/// fn fibonacci(n: u64) -> u64 {
+///     match n {
+///         0 | 1 => 1,
+///         n => fibonacci(n - 1) + fibonacci(n - 2),
+///     }
+/// }
+///
+/// # #[allow(dead_code)]
+/// fn bench_fibonacci(iai: &mut Iai, n: u64) {
+///     iai.run(|| fibonacci(n));
+/// }
+///
+/// iai::main!(bench_fibonacci(args = [10, 15, 20]));
+/// ```
+///
+/// Each input is run and reported as a separate benchmark, named after the
+/// function and the input expression (`bench_fibonacci/10`,
+/// `bench_fibonacci/15` and `bench_fibonacci/20` in the example above). Since
+/// the name is also used for the results files, string literals lose their
+/// quotes and any characters except letters, digits, `_`, `-` and `.` are
+/// replaced by `_` (so `"a/b"` becomes `a_b`).
+/// Input expressions are evaluated only when the corresponding benchmark is
+/// run.
+///
+/// # Groups
+///
+/// Benchmark functions can also be organized in groups that share the same
+/// configuration, using [`iai::group!`](crate::group!). Groups are listed in
+/// `iai::main!` in the same way as benchmark functions, and the two can be
+/// mixed freely.
+///
+/// # Registered benchmarks
+///
+/// When the `macros` feature is enabled, benchmark functions can also be
+/// registered with the `#[iai::bench]` attribute instead of being listed in
+/// `iai::main!`. Registered benchmarks are run after the listed ones, and
+/// `iai::main!()` can be used without arguments to run only the registered
+/// benchmarks.
+///
+/// # Custom `main` functions
+///
+/// Given a `name`, `iai::main!` defines a function with that name instead of
+/// `main`. The function runs the benchmarks and returns an
+/// [`iai::Error`](crate::Error) if they could not be run, or otherwise the exit
+/// code of the harness, which reports whether any regression was found. This
+/// allows running code before or after the benchmarks, and handling errors in
+/// a custom way:
+///
+/// ```no_run
+/// use iai::Iai;
+/// use std::process::ExitCode;
+///
+/// fn bench_method1(iai: &mut Iai) {
+///     iai.run(|| { /* ... */ });
+/// }
+///
+/// iai::main!(
+///     name = run_benchmarks;
+///     benches = bench_method1
+/// );
+///
+/// fn main() -> ExitCode {
+///     match run_benchmarks() {
+///         Ok(code) => code,
+///         Err(err) => {
+///             eprintln!("Benchmarks failed: {err}");
+///             ExitCode::FAILURE
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Configuration
+///
+/// A [`Config`](crate::Config) for all the benchmarks can be given with the
+/// same syntax accepted by [`iai::group!`](crate::group!). Groups inherit the
+/// options that they do not set themselves:
+///
+/// ```
+/// use iai::CacheGeometry;
+/// use iai::Config;
+/// use iai::Iai;
+///
+/// # #[allow(dead_code)]
+/// fn bench_method1(iai: &mut Iai) {
+///     iai.run(|| { /* ... */ });
+/// }
+///
+/// iai::main!(
+///     config = Config::new().ll_cache(CacheGeometry::new(256 * 1024, 8, 64));
+///     benches = bench_method1
+/// );
+/// ```
 #[macro_export]
 macro_rules! main {
-    ( $( $func_name:ident ),+ $(,)* ) => {
+    (
+        name = $name:ident ;
+        $( config = $config:expr ; )?
+        benches = $( $benches:tt )*
+    ) => {
+        fn $name() -> ::std::result::Result<::std::process::ExitCode, $crate::Error> {
+            let config = $crate::Config::new();
+            $( let config = $config; )?
+            let mut group = $crate::__private::Group::new(
+                ::std::option::Option::None,
+                config,
+            );
+            $crate::__iai_benchmarks!(group; $( $benches )*);
+            group.add_registered();
+            $crate::try_runner(&group)
+        }
+    };
+    (
+        config = $config:expr ;
+        benches = $( $benches:tt )*
+    ) => {
         fn main() -> ::std::process::ExitCode {
-            static BENCHMARKS: &[(&'static str, fn(&'_ mut $crate::Iai))]= &[$(
-                    (stringify!($func_name), $func_name),
-            )+];
-            $crate::runner(BENCHMARKS)
+            let mut group = $crate::__private::Group::new(
+                ::std::option::Option::None,
+                $config,
+            );
+            $crate::__iai_benchmarks!(group; $( $benches )*);
+            group.add_registered();
+            $crate::runner(&group)
+        }
+    };
+    ( $( $benches:tt )* ) => {
+        $crate::main!(config = $crate::Config::new(); benches = $( $benches )*);
+    };
+}
+
+/// Macro which defines a group of benchmarks sharing the same configuration.
+///
+/// A group has a name, an optional [`Config`](crate::Config) and a list of
+/// benchmarks, which use the same syntax accepted by [`iai::main!`](crate::main!):
+///
+/// ```
+/// use iai::Config;
+/// use iai::Iai;
+///
+/// # #[allow(dead_code)]
+/// fn bench_method1(iai: &mut Iai) {
+///     iai.run(|| { /* ... */ });
+/// }
+///
+/// # #[allow(dead_code)]
+/// fn bench_method2(iai: &mut Iai, n: u64) {
+///     iai.run(|| n * 2);
+/// }
+///
+/// iai::group!(
+///     name = my_group;
+///     config = Config::new().env("RUST_LOG", "off");
+///     benches = bench_method1, bench_method2(args = [1, 10, 100])
+/// );
+///
+/// iai::main!(my_group);
+/// ```
+///
+/// The benchmarks in a group are reported with the name of the group as a
+/// prefix (`my_group/bench_method1` in the example above), and their results are
+/// stored in a separate directory named after the group.
+#[macro_export]
+macro_rules! group {
+    (
+        name = $name:ident ;
+        $( config = $config:expr ; )?
+        benches = $( $benches:tt )+
+    ) => {
+        fn $name() -> $crate::__private::Group {
+            let config = $crate::Config::new();
+            $( let config = $config; )?
+            let mut group = $crate::__private::Group::new(
+                ::std::option::Option::Some(stringify!($name)),
+                config,
+            );
+            $crate::__iai_benchmarks!(group; $( $benches )+);
+            group
         }
     }
 }
+
+/// Adds the benchmarks listed in [`main!`] or [`group!`] to a group. Not part of the public API.
+///
+/// The entries are matched with a single repetition, rather than one at a time, so that the
+/// recursion limit does not depend on the number of benchmarks.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __iai_benchmarks {
+    (
+        $group:ident;
+        $( $func_name:ident $( ( args = [ $( $arg:expr ),* $(,)? ] ) )? ),* $(,)*
+    ) => {
+        $( $crate::__iai_benchmark!($group; $func_name $( ( $( $arg ),* ) )?); )*
+    };
+}
+
+/// Adds a single entry of [`main!`] or [`group!`] to a group. Not part of the public API.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __iai_benchmark {
+    ( $group:ident; $func_name:ident ( $( $arg:expr ),* ) ) => {
+        $(
+            $group.add_with_arg(
+                stringify!($func_name),
+                stringify!($arg),
+                |iai: &'_ mut $crate::Iai| $func_name(iai, $arg),
+            );
+        )*
+    };
+    ( $group:ident; $func_name:ident ) => {
+        $group.register(stringify!($func_name), $func_name);
+    };
+}
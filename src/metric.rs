@@ -0,0 +1,141 @@
+use crate::config::CycleModel;
+use crate::config::MeasurementConfig;
+use crate::config::Tool;
+use crate::results::Costs;
+use std::fmt;
+use std::str::FromStr;
+
+/// A metric reported by Iai for every benchmark.
+///
+/// More metrics may be added in the future, so the enum is non-exhaustive, and [`Metric::ALL`] is
+/// a slice rather than an array of a fixed length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Metric {
+    /// Number of instructions executed.
+    Instructions,
+    /// Number of memory accesses that hit the L1 caches.
+    L1Accesses,
+    /// Number of memory accesses that missed the L1 caches but hit the last-level cache.
+    L2Accesses,
+    /// Number of memory accesses that missed all the caches.
+    RamAccesses,
+    /// Estimated number of CPU cycles, derived from the number of cache hits and misses.
+    EstimatedCycles,
+    /// Number of conditional and indirect branches executed. Only available if branch
+    /// simulation is enabled.
+    Branches,
+    /// Number of conditional and indirect branches that were mispredicted. Only available if
+    /// branch simulation is enabled.
+    BranchMisses,
+    /// Number of heap allocations. Only available with [`Tool::Dhat`].
+    Allocations,
+    /// Total number of bytes allocated on the heap. Only available with [`Tool::Dhat`].
+    AllocatedBytes,
+    /// Maximum number of bytes allocated on the heap at the same time. Only available with
+    /// [`Tool::Dhat`].
+    PeakLiveBytes,
+}
+
+impl Metric {
+    /// All the metrics, in the order they are reported.
+    pub const ALL: &'static [Self] = &[
+        Self::Instructions,
+        Self::L1Accesses,
+        Self::L2Accesses,
+        Self::RamAccesses,
+        Self::EstimatedCycles,
+        Self::Branches,
+        Self::BranchMisses,
+        Self::Allocations,
+        Self::AllocatedBytes,
+        Self::PeakLiveBytes,
+    ];
+
+    /// Name of the metric, as accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Instructions => "instructions",
+            Self::L1Accesses => "l1-accesses",
+            Self::L2Accesses => "l2-accesses",
+            Self::RamAccesses => "ram-accesses",
+            Self::EstimatedCycles => "estimated-cycles",
+            Self::Branches => "branches",
+            Self::BranchMisses => "branch-misses",
+            Self::Allocations => "allocations",
+            Self::AllocatedBytes => "allocated-bytes",
+            Self::PeakLiveBytes => "peak-live-bytes",
+        }
+    }
+
+    /// Human-readable name of the metric, as shown in the reports.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Instructions => "Instructions",
+            Self::L1Accesses => "L1 Accesses",
+            Self::L2Accesses => "L2 Accesses",
+            Self::RamAccesses => "RAM Accesses",
+            Self::EstimatedCycles => "Estimated Cycles",
+            Self::Branches => "Branches",
+            Self::BranchMisses => "Branch Misses",
+            Self::Allocations => "Allocations",
+            Self::AllocatedBytes => "Allocated Bytes",
+            Self::PeakLiveBytes => "Peak Live Bytes",
+        }
+    }
+
+    /// Returns whether the metric is measured by the given tool with the given configuration.
+    pub(crate) fn is_measured(self, tool: Tool, config: &MeasurementConfig) -> bool {
+        match self {
+            Self::Allocations | Self::AllocatedBytes | Self::PeakLiveBytes => tool == Tool::Dhat,
+            _ if tool == Tool::Dhat => false,
+            Self::Branches | Self::BranchMisses => config.branch_sim,
+            _ => true,
+        }
+    }
+
+    /// Returns the value of the metric, or zero if it's not measured by the tool that obtained
+    /// the costs.
+    pub(crate) fn value(self, costs: &Costs, cycle_model: &CycleModel) -> u64 {
+        match (self, costs) {
+            (Self::Instructions, Costs::Cachegrind(stats)) => stats.instruction_reads,
+            (Self::L1Accesses, Costs::Cachegrind(stats)) => stats.summarize().l1_hits,
+            (Self::L2Accesses, Costs::Cachegrind(stats)) => stats.summarize().l3_hits,
+            (Self::RamAccesses, Costs::Cachegrind(stats)) => stats.summarize().ram_hits,
+            (Self::EstimatedCycles, Costs::Cachegrind(stats)) => {
+                stats.summarize().cycles(cycle_model)
+            }
+            (Self::Branches, Costs::Cachegrind(stats)) => stats.branches(),
+            (Self::BranchMisses, Costs::Cachegrind(stats)) => stats.branch_misses(),
+            (Self::Allocations, Costs::Dhat(stats)) => stats.allocations,
+            (Self::AllocatedBytes, Costs::Dhat(stats)) => stats.allocated_bytes,
+            (Self::PeakLiveBytes, Costs::Dhat(stats)) => stats.peak_live_bytes,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|metric| metric.name() == s)
+            .ok_or_else(|| {
+                let names = Self::ALL
+                    .iter()
+                    .map(|metric| metric.name())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("unknown metric '{s}' (expected one of: {names})")
+            })
+    }
+}
@@ -0,0 +1,361 @@
+//! Types used by [`iai::main!`](crate::main!) and [`iai::group!`](crate::group!) to collect the
+//! benchmarks defined by the user. None of these are part of the public API.
+
+use crate::Iai;
+use crate::command::Command;
+use crate::config::Config;
+#[cfg(feature = "criterion")]
+use crate::criterion::Criterion;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A benchmark defined by the user, together with the group it belongs to.
+pub(crate) struct UserBenchmark {
+    pub(crate) group: Option<&'static str>,
+    pub(crate) name: String,
+    pub(crate) config: Rc<Config>,
+    pub(crate) func: BenchmarkFn,
+}
+
+/// What is measured by a benchmark.
+pub(crate) enum BenchmarkFn {
+    /// A benchmark function, run in a separate process of the benchmark harness.
+    Function(Box<dyn Fn(&mut Iai)>),
+    /// A function returning an external command, which is measured from start to finish.
+    Command(Box<dyn Fn() -> Command>),
+}
+
+/// Identifies a benchmark by its name and the group it belongs to, whether or not it's defined
+/// in the benchmark harness: benchmarks compared with `--compare` may only exist as results.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct BenchmarkId<'a> {
+    pub(crate) group: Option<&'a str>,
+    pub(crate) name: &'a str,
+}
+
+impl BenchmarkId<'_> {
+    /// Unique identifier of the benchmark, used to select it with `--iai-run`.
+    pub(crate) fn id(&self) -> String {
+        match self.group {
+            Some(group) => format!("{group}/{}", self.name),
+            None => self.name.to_owned(),
+        }
+    }
+
+    /// Identifier of a region of the benchmark, as reported, or of the whole benchmark if
+    /// `region` is `None`.
+    pub(crate) fn region_id(&self, region: Option<&str>) -> String {
+        match region {
+            Some(region) => format!("{}/{region}", self.id()),
+            None => self.id(),
+        }
+    }
+
+    /// Directory of the valgrind output files, relative to the iai directory.
+    pub(crate) fn output_dir(&self) -> PathBuf {
+        self.group.map(PathBuf::from).unwrap_or_default()
+    }
+}
+
+impl UserBenchmark {
+    /// Returns the name of the benchmark, together with the group it belongs to.
+    pub(crate) fn bench_id(&self) -> BenchmarkId<'_> {
+        BenchmarkId {
+            group: self.group,
+            name: &self.name,
+        }
+    }
+
+    /// Unique identifier of the benchmark, used to select it with `--iai-run`.
+    pub(crate) fn id(&self) -> String {
+        self.bench_id().id()
+    }
+
+    /// Returns whether the benchmark has the given identifier, without allocating.
+    pub(crate) fn has_id(&self, id: &str) -> bool {
+        match self.group {
+            Some(group) => id
+                .strip_prefix(group)
+                .and_then(|id| id.strip_prefix('/'))
+                .is_some_and(|name| name == self.name),
+            None => id == self.name,
+        }
+    }
+
+    /// Directory of the valgrind output files, relative to the iai directory.
+    pub(crate) fn output_dir(&self) -> PathBuf {
+        self.bench_id().output_dir()
+    }
+}
+
+impl fmt::Debug for UserBenchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserBenchmark")
+            .field("group", &self.group)
+            .field("name", &self.name)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A list of benchmarks sharing the same configuration.
+///
+/// The benchmarks passed directly to `iai::main!` belong to an unnamed group, with the
+/// configuration given to `iai::main!`.
+#[derive(Debug)]
+pub struct Group {
+    name: Option<&'static str>,
+    config: Rc<Config>,
+    benchmarks: Vec<UserBenchmark>,
+}
+
+impl Group {
+    pub fn new(name: Option<&'static str>, config: Config) -> Self {
+        Self {
+            name,
+            config: Rc::new(config),
+            benchmarks: Vec::new(),
+        }
+    }
+
+    pub fn add<F>(&mut self, name: &'static str, func: F)
+    where
+        F: Fn(&mut Iai) + 'static,
+    {
+        self.push(name.to_owned(), BenchmarkFn::Function(Box::new(func)));
+    }
+
+    /// Adds a benchmark of a function with one of its inputs, named after the function and the
+    /// source code of the input expression.
+    pub fn add_with_arg<F>(&mut self, func_name: &'static str, arg: &'static str, func: F)
+    where
+        F: Fn(&mut Iai) + 'static,
+    {
+        let name = format!("{func_name}/{}", arg_label(arg));
+        self.push(name, BenchmarkFn::Function(Box::new(func)));
+    }
+
+    pub fn add_command<F>(&mut self, name: &'static str, func: F)
+    where
+        F: Fn() -> Command + 'static,
+    {
+        self.push(name.to_owned(), BenchmarkFn::Command(Box::new(func)));
+    }
+
+    fn push(&mut self, name: String, func: BenchmarkFn) {
+        self.benchmarks.push(UserBenchmark {
+            group: self.name,
+            name,
+            config: Rc::clone(&self.config),
+            func,
+        });
+    }
+
+    pub fn register<T, M>(&mut self, name: &'static str, item: T)
+    where
+        T: Register<M>,
+    {
+        item.register(self, name)
+    }
+
+    /// Adds the benchmarks registered with `#[iai::bench]`, in the order they are defined in
+    /// every file.
+    pub fn add_registered(&mut self) {
+        #[cfg(feature = "macros")]
+        {
+            let mut registrations = BENCHMARKS.iter().collect::<Vec<_>>();
+            registrations.sort_by_key(|registration| (registration.file, registration.line));
+            for registration in registrations {
+                (registration.register)(self);
+            }
+        }
+    }
+
+    pub(crate) fn benchmarks(&self) -> &[UserBenchmark] {
+        &self.benchmarks
+    }
+}
+
+/// Returns the label of an input expression in the name of a benchmark, which is also used in the
+/// names of its results files.
+///
+/// String literals lose their quotes, and the whitespace between tokens of other expressions is
+/// removed. The label is then made safe to use in a path with [`path_component()`].
+fn arg_label(arg: &str) -> String {
+    let literal = arg.strip_prefix('"').and_then(|arg| arg.strip_suffix('"'));
+    match literal {
+        Some(literal) => path_component(literal.chars()),
+        None => path_component(arg.chars().filter(|c| !c.is_whitespace())),
+    }
+}
+
+/// Returns a part of the name of a benchmark which can't add directories to the path of the
+/// results or point outside of it.
+///
+/// Every run of characters other than letters, digits, `_`, `-` and `.` is replaced by a single
+/// `_`, leading and trailing `_` are removed, and names made only of dots, like `.` and `..`, have
+/// every dot replaced by `_`.
+pub(crate) fn path_component<I: IntoIterator<Item = char>>(chars: I) -> String {
+    let mut label = String::new();
+    for c in chars {
+        if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') {
+            label.push(c);
+        } else if !label.ends_with('_') {
+            label.push('_');
+        }
+    }
+    let label = label.trim_matches('_');
+    // Empty names, `.` and `..` don't name a file
+    if label.chars().all(|c| c == '.') {
+        "_".repeat(label.len().max(1))
+    } else {
+        label.to_owned()
+    }
+}
+
+/// A benchmark function registered with `#[iai::bench]`.
+#[cfg(feature = "macros")]
+#[derive(Debug)]
+pub struct Registration {
+    /// Location of the function, used to add the benchmarks in the order they are defined.
+    pub file: &'static str,
+    pub line: u32,
+    /// Adds the benchmarks of the function to the group passed to `iai::main!`.
+    pub register: fn(&mut Group),
+}
+
+/// Benchmark functions registered with `#[iai::bench]`, in no particular order.
+#[cfg(feature = "macros")]
+#[linkme::distributed_slice]
+pub static BENCHMARKS: [Registration];
+
+/// Items that can be listed in `iai::main!` and `iai::group!`.
+///
+/// The type parameter `M` is only used to disambiguate between the implementations for the
+/// different function signatures.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is neither a benchmark function nor a benchmark group",
+    note = "benchmark functions must have the signature `fn(&mut iai::Iai)`",
+    note = "external commands must be returned by functions with the signature `fn() -> iai::Command`",
+    note = "benchmark groups must be defined with `iai::group!`"
+)]
+pub trait Register<M> {
+    fn register(self, group: &mut Group, name: &'static str);
+}
+
+#[derive(Debug)]
+pub enum FunctionMarker {}
+
+#[derive(Debug)]
+pub enum GroupMarker {}
+
+#[derive(Debug)]
+pub enum CommandMarker {}
+
+#[cfg(feature = "criterion")]
+#[derive(Debug)]
+pub enum CriterionMarker {}
+
+impl<F> Register<FunctionMarker> for F
+where
+    F: Fn(&mut Iai) + 'static,
+{
+    fn register(self, group: &mut Group, name: &'static str) {
+        group.add(name, self)
+    }
+}
+
+impl<F> Register<GroupMarker> for F
+where
+    F: FnOnce() -> Group,
+{
+    fn register(self, group: &mut Group, _name: &'static str) {
+        // Nested groups inherit the options they do not set from the enclosing group
+        let benchmarks = self()
+            .benchmarks
+            .into_iter()
+            .map(|benchmark| UserBenchmark {
+                config: Rc::new(benchmark.config.inherit(&group.config)),
+                ..benchmark
+            });
+        group.benchmarks.extend(benchmarks)
+    }
+}
+
+impl<F> Register<CommandMarker> for F
+where
+    F: Fn() -> Command + 'static,
+{
+    fn register(self, group: &mut Group, name: &'static str) {
+        group.add_command(name, self)
+    }
+}
+
+#[cfg(feature = "criterion")]
+impl<F> Register<CriterionMarker> for F
+where
+    F: Fn(&mut Criterion<'_>) + Clone + 'static,
+{
+    fn register(self, group: &mut Group, _name: &'static str) {
+        // The function is called once to find the IDs of its benchmarks, and then every
+        // benchmark calls it again to run only the benchmark with its ID
+        let mut criterion = Criterion::default();
+        self(&mut criterion);
+        for id in criterion.into_ids() {
+            let (func, name) = (self.clone(), id.clone());
+            let benchmark = move |iai: &mut Iai| func(&mut Criterion::run(&id, iai));
+            group.push(name, BenchmarkFn::Function(Box::new(benchmark)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::arg_label;
+
+    #[test]
+    fn arg_label_strips_quotes() {
+        assert_eq!(arg_label(r#""hello""#), "hello");
+        assert_eq!(arg_label(r#""hello world""#), "hello_world");
+        assert_eq!(arg_label(r#""a"b""#), "a_b");
+    }
+
+    #[test]
+    fn arg_label_removes_whitespace() {
+        assert_eq!(arg_label("1 + 2"), "1_2");
+        assert_eq!(arg_label("vec![1, 2, 3]"), "vec_1_2_3");
+        assert_eq!(arg_label("  foo  "), "foo");
+    }
+
+    #[test]
+    fn arg_label_replaces_slashes() {
+        assert_eq!(arg_label(r#""a/b""#), "a_b");
+        assert_eq!(arg_label(r#""/abs/path/""#), "abs_path");
+        assert_eq!(arg_label(r#""a\\b""#), "a_b");
+        assert_eq!(arg_label(r#""../x""#), ".._x");
+    }
+
+    #[test]
+    fn arg_label_escapes_dots() {
+        assert_eq!(arg_label(r#"".""#), "_");
+        assert_eq!(arg_label(r#""..""#), "__");
+        assert_eq!(arg_label(".."), "__");
+        assert_eq!(arg_label(r#""/../""#), "__");
+        assert_eq!(arg_label("0..10"), "0..10");
+        assert_eq!(arg_label("1.5"), "1.5");
+    }
+
+    #[test]
+    fn arg_label_keeps_non_ascii_letters() {
+        assert_eq!(arg_label(r#""héllo wörld""#), "héllo_wörld");
+        assert_eq!(arg_label(r#""日本語""#), "日本語");
+        assert_eq!(arg_label(r#""→""#), "_");
+    }
+
+    #[test]
+    fn arg_label_is_never_empty() {
+        assert_eq!(arg_label(r#""""#), "_");
+        assert_eq!(arg_label("()"), "_");
+    }
+}
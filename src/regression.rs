@@ -0,0 +1,313 @@
+//! Regression thresholds, and the checks of the results against them.
+
+use crate::Args;
+use crate::Config;
+use crate::Error;
+use crate::Metric;
+use crate::registry::BenchmarkId;
+use crate::report;
+use crate::results::Stats;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+/// Exit code used when one or more benchmarks exceed their regression thresholds.
+const REGRESSION_EXIT_CODE: u8 = 3;
+
+#[derive(Clone, Debug)]
+pub(crate) struct RegressionThreshold {
+    benchmark: Option<String>,
+    metric: Metric,
+    percent: f64,
+}
+
+impl FromStr for RegressionThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (benchmark, threshold) = match s.rsplit_once(':') {
+            Some((benchmark, threshold)) => (Some(benchmark.to_owned()), threshold),
+            None => (None, s),
+        };
+        let (metric, percent) = threshold
+            .split_once('=')
+            .ok_or_else(|| format!("expected '[BENCHMARK:]METRIC=PERCENT', got '{s}'"))?;
+        let metric = metric.parse()?;
+        let percent = match percent.strip_suffix('%').unwrap_or(percent).parse::<f64>() {
+            // Negative thresholds would be exceeded by every benchmark, and NaN or infinite ones by
+            // none
+            Ok(value) if value.is_finite() && value >= 0.0 => value,
+            Ok(_) => {
+                return Err(format!(
+                    "invalid percentage '{percent}': must be a finite, non-negative number"
+                ));
+            }
+            Err(err) => return Err(format!("invalid percentage '{percent}': {err}")),
+        };
+        Ok(Self {
+            benchmark,
+            metric,
+            percent,
+        })
+    }
+}
+
+/// Prints the regressions that exceeded their thresholds, if any, and returns the exit code of
+/// the harness.
+pub(crate) fn report_regressions(
+    regressions: Vec<(String, Metric, Option<f64>, f64)>,
+) -> Result<ExitCode, Error> {
+    if regressions.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    error!(
+        "{} regression(s) exceeded their thresholds:",
+        regressions.len()
+    );
+    for (id, metric, change, threshold) in regressions {
+        match change {
+            Some(change) => eprintln!(
+                "  {id}: {} changed by {change:+.2}% (threshold: {threshold:+.2}%)",
+                metric.label()
+            ),
+            None => eprintln!(
+                "  {id}: {} increased from zero (threshold: {threshold:+.2}%)",
+                metric.label()
+            ),
+        }
+    }
+    Ok(ExitCode::from(REGRESSION_EXIT_CODE))
+}
+
+/// Returns the regression thresholds that apply to a benchmark with the given configuration, for
+/// every metric that has one.
+pub(crate) fn regression_thresholds(
+    args: &Args,
+    benchmark: BenchmarkId<'_>,
+    config: &Config,
+    region: Option<&str>,
+) -> Vec<(Metric, f64)> {
+    Metric::ALL
+        .iter()
+        .copied()
+        .filter_map(|metric| {
+            regression_threshold(args, benchmark, config, region, metric)
+                .map(|percent| (metric, percent))
+        })
+        .collect()
+}
+
+/// Returns the metrics of a benchmark that exceed their regression thresholds, together with
+/// their change (`None` for increases from zero, which exceed any threshold) and threshold.
+pub(crate) fn find_regressions(
+    args: &Args,
+    benchmark: BenchmarkId<'_>,
+    config: &Config,
+    region: Option<&str>,
+    stats: &Stats,
+) -> Vec<(String, Metric, Option<f64>, f64)> {
+    let Some(old) = &stats.old else {
+        return Vec::new();
+    };
+
+    let mut regressions = Vec::new();
+    for &metric in Metric::ALL {
+        if !metric.is_measured(stats.tool, &stats.config) {
+            continue;
+        }
+        let Some(threshold) = regression_threshold(args, benchmark, config, region, metric) else {
+            continue;
+        };
+        let cycle_model = &stats.config.cycle_model;
+        let new = metric.value(&stats.new, cycle_model);
+        let old = metric.value(old, cycle_model);
+        let change = report::percentage_change(new, old);
+        if change.map_or(new > old, |change| change > threshold) {
+            regressions.push((benchmark.region_id(region), metric, change, threshold));
+        }
+    }
+    regressions
+}
+
+/// Returns the regression threshold for the given benchmark (or region of the benchmark), run with
+/// the given configuration, and metric.
+///
+/// Thresholds given on the command line for a specific benchmark take precedence over the ones
+/// from the configuration of the benchmark, which in turn take precedence over the thresholds
+/// given on the command line for all benchmarks.
+fn regression_threshold(
+    args: &Args,
+    benchmark: BenchmarkId<'_>,
+    config: &Config,
+    region: Option<&str>,
+    metric: Metric,
+) -> Option<f64> {
+    let id = benchmark.region_id(region);
+    let from_args = |for_benchmark: bool| {
+        args.regression_thresholds
+            .iter()
+            .rev()
+            .find(|threshold| {
+                threshold.metric == metric
+                    && match &threshold.benchmark {
+                        Some(benchmark) => for_benchmark && *benchmark == id,
+                        None => !for_benchmark,
+                    }
+            })
+            .map(|threshold| threshold.percent)
+    };
+    let from_config = || {
+        config
+            .regression_thresholds
+            .iter()
+            .find(|(m, _)| *m == metric)
+            .map(|(_, percent)| *percent)
+    };
+
+    from_args(true)
+        .or_else(from_config)
+        .or_else(|| from_args(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegressionThreshold;
+    use super::find_regressions;
+    use super::regression_threshold;
+    use crate::Args;
+    use crate::Config;
+    use crate::Metric;
+    use crate::Tool;
+    use crate::cachegrind::CachegrindStats;
+    use crate::registry::BenchmarkId;
+    use crate::results::Costs;
+    use crate::results::Stats;
+    use clap::Parser;
+
+    const FIB: BenchmarkId<'static> = BenchmarkId {
+        group: None,
+        name: "fib",
+    };
+
+    fn args(thresholds: &[&str]) -> Args {
+        let args = thresholds
+            .iter()
+            .map(|threshold| format!("--regression-threshold={threshold}"));
+        Args::try_parse_from(["bench"].into_iter().map(String::from).chain(args)).unwrap()
+    }
+
+    fn instructions(instructions: u64) -> Costs {
+        Costs::Cachegrind(CachegrindStats::from_events(&["Ir"], &[instructions]))
+    }
+
+    fn stats(new: u64, old: Option<u64>) -> Stats {
+        Stats {
+            new: instructions(new),
+            old: old.map(instructions),
+            tool: Tool::Cachegrind,
+            config: Config::new().measurement(),
+            regions: Vec::new(),
+            functions: Vec::new(),
+            old_functions: Vec::new(),
+            parts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse_threshold() {
+        let threshold = "instructions=5".parse::<RegressionThreshold>().unwrap();
+        assert_eq!(threshold.benchmark, None);
+        assert_eq!(threshold.metric, Metric::Instructions);
+        assert_eq!(threshold.percent, 5.0);
+
+        let threshold = "fib:l1-accesses=2.5%"
+            .parse::<RegressionThreshold>()
+            .unwrap();
+        assert_eq!(threshold.benchmark.as_deref(), Some("fib"));
+        assert_eq!(threshold.metric, Metric::L1Accesses);
+        assert_eq!(threshold.percent, 2.5);
+
+        // Only the last `:` separates the benchmark from the metric
+        let threshold = "group/fib::10:instructions=0"
+            .parse::<RegressionThreshold>()
+            .unwrap();
+        assert_eq!(threshold.benchmark.as_deref(), Some("group/fib::10"));
+        assert_eq!(threshold.percent, 0.0);
+    }
+
+    #[test]
+    fn parse_invalid_threshold() {
+        for invalid in [
+            "instructions",
+            "unknown=5",
+            "instructions=abc",
+            "fib:instructions=",
+        ] {
+            assert!(invalid.parse::<RegressionThreshold>().is_err(), "{invalid}");
+        }
+        for percent in ["-5", "NaN", "inf", "-inf"] {
+            let err = format!("instructions={percent}")
+                .parse::<RegressionThreshold>()
+                .unwrap_err();
+            assert!(err.contains(&format!("'{percent}'")), "{err}");
+        }
+    }
+
+    #[test]
+    fn threshold_precedence() {
+        let threshold = |args: &Args, config: &Config| {
+            regression_threshold(args, FIB, config, None, Metric::Instructions)
+        };
+        let config = Config::new().regression_threshold(Metric::Instructions, 2.0);
+
+        assert_eq!(threshold(&args(&[]), &Config::new()), None);
+        assert_eq!(
+            threshold(&args(&["instructions=1"]), &Config::new()),
+            Some(1.0)
+        );
+        assert_eq!(threshold(&args(&["instructions=1"]), &config), Some(2.0));
+        let both = args(&["fib:instructions=3", "instructions=1"]);
+        assert_eq!(threshold(&both, &config), Some(3.0));
+        // Thresholds for other benchmarks or metrics don't apply
+        let other = args(&["fibonacci:instructions=3", "fib:branches=4"]);
+        assert_eq!(threshold(&other, &config), Some(2.0));
+        // The last threshold given for the same benchmark and metric wins
+        let repeated = args(&["instructions=1", "instructions=5"]);
+        assert_eq!(threshold(&repeated, &Config::new()), Some(5.0));
+    }
+
+    #[test]
+    fn regions_have_their_own_thresholds() {
+        let args = args(&["fib/sort:instructions=3", "instructions=1"]);
+        let threshold =
+            |region| regression_threshold(&args, FIB, &Config::new(), region, Metric::Instructions);
+        assert_eq!(threshold(Some("sort")), Some(3.0));
+        assert_eq!(threshold(Some("search")), Some(1.0));
+        assert_eq!(threshold(None), Some(1.0));
+    }
+
+    #[test]
+    fn find_exceeded_thresholds() {
+        let args = args(&["instructions=10"]);
+        let find = |stats| find_regressions(&args, FIB, &Config::new(), None, &stats);
+
+        assert!(find(stats(110, Some(100))).is_empty());
+        assert!(find(stats(50, Some(100))).is_empty());
+        assert!(find(stats(111, None)).is_empty());
+
+        let regressions = find(stats(111, Some(100)));
+        assert_eq!(regressions.len(), 1);
+        let (id, metric, change, threshold) = &regressions[0];
+        assert_eq!(
+            (id.as_str(), *metric, *threshold),
+            ("fib", Metric::Instructions, 10.0)
+        );
+        assert!((change.unwrap() - 11.0).abs() < 1e-9);
+
+        // Any increase from zero exceeds the threshold
+        let regressions = find(stats(1, Some(0)));
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].2, None);
+        assert!(find(stats(0, Some(0))).is_empty());
+    }
+}
@@ -0,0 +1,874 @@
+use crate::cachegrind::CachegrindStats;
+use crate::cachegrind::FunctionCosts;
+use crate::config::CycleModel;
+use crate::config::MeasurementConfig;
+use crate::config::Tool;
+use crate::error::Error;
+use crate::json;
+use crate::metric::Metric;
+use crate::registry::BenchmarkId;
+use crate::results::Costs;
+use crate::results::Stats;
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+/// Format of the results printed on standard output.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// One JSON object per benchmark, one per line
+    Json,
+    /// Markdown tables, suitable for pull request comments
+    Markdown,
+}
+
+/// Number of callees shown for every function in the call graph of the text reports.
+const CALL_GRAPH_CALLEES: usize = 5;
+
+/// Returns the relative change from `old` to `new`, as a percentage, or `None` if only `old` is
+/// zero and the change can't be expressed relative to it.
+pub(crate) fn percentage_change(new: u64, old: u64) -> Option<f64> {
+    if new == old {
+        return Some(0.0);
+    }
+    if old == 0 {
+        return None;
+    }
+    let new: f64 = new as f64;
+    let old: f64 = old as f64;
+
+    let diff = (new - old) / old;
+    Some(diff * 100.0)
+}
+
+/// Prints the results of the benchmarks on standard output.
+#[derive(Clone, Debug)]
+pub(crate) struct Reporter {
+    format: OutputFormat,
+    top_functions: usize,
+    markdown_file: Option<PathBuf>,
+    /// Markdown report written so far, if it's written to a file.
+    markdown: String,
+    /// Number of benchmarks and regions in the Markdown report, including the removed ones.
+    benchmarks: usize,
+    /// Number of benchmarks and regions with at least one metric marked as a regression.
+    regressions: usize,
+    /// Number of benchmarks and regions with at least one metric marked as an improvement.
+    improvements: usize,
+}
+
+impl Reporter {
+    pub(crate) fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            top_functions: 0,
+            markdown_file: None,
+            markdown: String::new(),
+            benchmarks: 0,
+            regressions: 0,
+            improvements: 0,
+        }
+    }
+
+    /// Sets the number of functions shown in the lists of the most expensive functions and of
+    /// the functions that changed the most. If zero (the default), the lists are not shown.
+    ///
+    /// Only applies to the text format.
+    pub(crate) fn top_functions(&mut self, top_functions: usize) -> &mut Self {
+        self.top_functions = top_functions;
+        self
+    }
+
+    /// Sets the file where a Markdown report is written, in addition to the results printed on
+    /// standard output. The file is written by [`finish()`](Self::finish).
+    pub(crate) fn markdown_file(&mut self, markdown_file: Option<PathBuf>) -> &mut Self {
+        self.markdown_file = markdown_file;
+        self
+    }
+
+    /// Prints the header of a benchmark, or of a region of a benchmark, before it's run.
+    pub(crate) fn print_header(&self, benchmark: BenchmarkId<'_>, region: Option<&str>) {
+        match self.format {
+            OutputFormat::Text => println!("{}", benchmark.region_id(region)),
+            OutputFormat::Json | OutputFormat::Markdown => {}
+        }
+    }
+
+    /// Prints the results of a benchmark, or of a region of a benchmark.
+    ///
+    /// `thresholds` are the regression thresholds that apply to the benchmark, used to mark the
+    /// regressions and improvements in the Markdown report.
+    pub(crate) fn print_stats(
+        &mut self,
+        benchmark: BenchmarkId<'_>,
+        region: Option<&str>,
+        stats: &Stats,
+        thresholds: &[(Metric, f64)],
+    ) {
+        self.print_results(benchmark, region, stats, thresholds, false);
+    }
+
+    /// Reports that a benchmark, or a region of a benchmark, has results in the new set only,
+    /// when comparing two sets of saved results, and prints them like
+    /// [`print_stats()`](Self::print_stats).
+    pub(crate) fn print_added(
+        &mut self,
+        benchmark: BenchmarkId<'_>,
+        region: Option<&str>,
+        stats: &Stats,
+        thresholds: &[(Metric, f64)],
+    ) {
+        self.print_results(benchmark, region, stats, thresholds, true);
+    }
+
+    fn print_results(
+        &mut self,
+        benchmark: BenchmarkId<'_>,
+        region: Option<&str>,
+        stats: &Stats,
+        thresholds: &[(Metric, f64)],
+        added: bool,
+    ) {
+        match self.format {
+            OutputFormat::Text => {
+                if added {
+                    println!("  Added (only found in the new results)");
+                }
+                print_text(stats, self.top_functions)
+            }
+            OutputFormat::Json => println!("{}", json_record(benchmark, region, stats, added)),
+            OutputFormat::Markdown => {}
+        }
+
+        if self.format == OutputFormat::Markdown || self.markdown_file.is_some() {
+            let section = self.markdown_section(benchmark, region, stats, thresholds, added);
+            self.push_markdown(&section);
+        }
+    }
+
+    /// Prints the calibration subtracted from the results of the benchmarks, which measures the
+    /// overhead of the harness. `description` tells which benchmarks it applies to.
+    pub(crate) fn print_calibration(&mut self, description: &str, stats: &Stats) {
+        match self.format {
+            OutputFormat::Text => {
+                // The functions of the harness are not interesting
+                println!("{description}");
+                print_text(
+                    &Stats {
+                        functions: Vec::new(),
+                        parts: Vec::new(),
+                        ..stats.clone()
+                    },
+                    0,
+                );
+            }
+            OutputFormat::Json => println!(
+                "{}",
+                json::Value::object([
+                    ("calibration", description.into()),
+                    ("tool", stats.tool.name().into()),
+                    ("config", json_config(&stats.config)),
+                    ("stats", json_costs(&stats.new, &stats.config.cycle_model)),
+                    (
+                        "baseline",
+                        stats
+                            .old
+                            .as_ref()
+                            .map(|old| json_costs(old, &stats.config.cycle_model))
+                            .into(),
+                    ),
+                ])
+            ),
+            OutputFormat::Markdown => {}
+        }
+
+        if self.format == OutputFormat::Markdown || self.markdown_file.is_some() {
+            let costs = Metric::ALL
+                .iter()
+                .copied()
+                .filter(|metric| metric.is_measured(stats.tool, &stats.config))
+                .map(|metric| {
+                    let value = metric.value(&stats.new, &stats.config.cycle_model);
+                    format!("{} {value}", metric.label())
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            self.push_markdown(&format!(
+                "_{description} ({}), subtracted from every benchmark: {costs}._\n\n",
+                stats.tool
+            ));
+        }
+    }
+
+    /// Reports that a benchmark, or a region of a benchmark, has results in the old set only,
+    /// when comparing two sets of saved results.
+    pub(crate) fn print_removed(&mut self, benchmark: BenchmarkId<'_>, region: Option<&str>) {
+        match self.format {
+            OutputFormat::Text => println!("  Removed (only found in the old results)"),
+            OutputFormat::Json => println!(
+                "{}",
+                json::Value::object([
+                    ("id", benchmark.region_id(region).into()),
+                    ("group", benchmark.group.into()),
+                    ("name", benchmark.name.into()),
+                    ("region", region.into()),
+                    ("removed", true.into()),
+                ])
+            ),
+            OutputFormat::Markdown => {}
+        }
+
+        if self.format == OutputFormat::Markdown || self.markdown_file.is_some() {
+            let section = format!("#### `{}`\n\nRemoved.\n\n", benchmark.region_id(region));
+            self.push_markdown(&section);
+            self.benchmarks += 1;
+        }
+    }
+
+    fn push_markdown(&mut self, section: &str) {
+        if self.format == OutputFormat::Markdown {
+            print!("{section}");
+        }
+        if self.markdown_file.is_some() {
+            self.markdown.push_str(section);
+        }
+    }
+
+    /// Prints the summary of the Markdown report, and writes the report to its file, if any.
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        if self.format != OutputFormat::Markdown && self.markdown_file.is_none() {
+            return Ok(());
+        }
+
+        let summary = format!(
+            "**Summary:** {} benchmark(s), {} with regressions \u{1f534}, {} with improvements \u{1f7e2}\n",
+            self.benchmarks, self.regressions, self.improvements
+        );
+        if self.format == OutputFormat::Markdown {
+            print!("{summary}");
+        }
+        if let Some(markdown_file) = &self.markdown_file {
+            fs::write(markdown_file, format!("{}{summary}", self.markdown))
+                .map_err(|err| Error::io(markdown_file, err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns a table with the results of a benchmark, marking the changes larger than the
+    /// regression threshold of their metric (or all the changes, for metrics without a
+    /// threshold) as regressions or improvements.
+    fn markdown_section(
+        &mut self,
+        benchmark: BenchmarkId<'_>,
+        region: Option<&str>,
+        stats: &Stats,
+        thresholds: &[(Metric, f64)],
+        added: bool,
+    ) -> String {
+        let mut section = String::new();
+        let _ = writeln!(section, "#### `{}`\n", benchmark.region_id(region));
+        if added {
+            section.push_str("Added.\n\n");
+        }
+        section.push_str("| Metric | Current | Baseline | Change |\n");
+        section.push_str("| :--- | ---: | ---: | ---: |\n");
+
+        let cycle_model = &stats.config.cycle_model;
+        let (mut regressed, mut improved) = (false, false);
+        for &metric in Metric::ALL {
+            if !metric.is_measured(stats.tool, &stats.config) {
+                continue;
+            }
+            let new = metric.value(&stats.new, cycle_model);
+            let Some(old) = stats.old.as_ref().map(|old| metric.value(old, cycle_model)) else {
+                let _ = writeln!(section, "| {} | {new} | | |", metric.label());
+                continue;
+            };
+
+            let change = if new == old {
+                "No change".to_owned()
+            } else {
+                let percent = percentage_change(new, old);
+                let threshold = thresholds
+                    .iter()
+                    .find(|(m, _)| *m == metric)
+                    .map_or(0.0, |(_, threshold)| *threshold);
+                // An increase from zero exceeds any threshold
+                let (exceeds, falls_below) = match percent {
+                    None => (true, false),
+                    Some(percent) => (percent > threshold, percent < -threshold),
+                };
+                let mark = if exceeds {
+                    regressed = true;
+                    " \u{1f534}"
+                } else if falls_below {
+                    improved = true;
+                    " \u{1f7e2}"
+                } else {
+                    ""
+                };
+                match percent {
+                    Some(percent) => format!("{percent:+.2}%{mark}"),
+                    None => format!("Was zero{mark}"),
+                }
+            };
+            let _ = writeln!(section, "| {} | {new} | {old} | {change} |", metric.label());
+        }
+        section.push('\n');
+
+        self.benchmarks += 1;
+        self.regressions += usize::from(regressed);
+        self.improvements += usize::from(improved);
+        section
+    }
+}
+
+fn print_text(stats: &Stats, top_functions: usize) {
+    fn signed_short(n: f64) -> String {
+        let n_abs = n.abs();
+
+        if n_abs < 10.0 {
+            format!("{:+.6}", n)
+        } else if n_abs < 100.0 {
+            format!("{:+.5}", n)
+        } else if n_abs < 1000.0 {
+            format!("{:+.4}", n)
+        } else if n_abs < 10000.0 {
+            format!("{:+.3}", n)
+        } else if n_abs < 100000.0 {
+            format!("{:+.2}", n)
+        } else if n_abs < 1000000.0 {
+            format!("{:+.1}", n)
+        } else {
+            format!("{:+.0}", n)
+        }
+    }
+
+    fn percentage_diff(new: u64, old: u64) -> String {
+        if new == old {
+            return " (No change)".to_owned();
+        }
+
+        match percentage_change(new, old) {
+            Some(change) => format!(" ({:>+6}%)", signed_short(change)),
+            None => " (Was zero)".to_owned(),
+        }
+    }
+
+    for &metric in Metric::ALL {
+        if !metric.is_measured(stats.tool, &stats.config) {
+            continue;
+        }
+        let new = metric.value(&stats.new, &stats.config.cycle_model);
+        let old = stats
+            .old
+            .as_ref()
+            .map(|old| metric.value(old, &stats.config.cycle_model));
+
+        println!(
+            "  {:<18}{:>15}{}",
+            format!("{}:", metric.label()),
+            new,
+            match old {
+                Some(old) => percentage_diff(new, old),
+                None => "".to_owned(),
+            }
+        );
+    }
+
+    if stats.parts.len() > 1 {
+        print_parts(stats);
+    }
+    if top_functions > 0
+        && stats
+            .functions
+            .iter()
+            .any(|function| function.inclusive.is_some())
+    {
+        print_call_graph(&stats.functions, top_functions);
+    }
+    if top_functions > 0 && !stats.functions.is_empty() {
+        print_top_functions(&stats.functions, top_functions);
+        if stats.old.is_some() {
+            print_changed_functions(&stats.functions, &stats.old_functions, top_functions);
+        }
+    }
+    println!();
+}
+
+/// Prints the costs of every process or thread, and their share of the total.
+fn print_parts(stats: &Stats) {
+    // DHAT does not count instructions, so processes are compared by the memory they allocate
+    let metric = match stats.tool {
+        Tool::Dhat => Metric::AllocatedBytes,
+        _ => Metric::Instructions,
+    };
+    let cost = |part_stats| metric.value(part_stats, &stats.config.cycle_model);
+    let total = stats
+        .parts
+        .iter()
+        .map(|part| cost(&part.stats))
+        .sum::<u64>();
+
+    println!(
+        "  Processes and threads ({}):",
+        metric.label().to_lowercase()
+    );
+    println!("  {:>15} {:>8}  Process or thread", "Cost", "Share");
+    for part in &stats.parts {
+        let share = match total {
+            0 => 0.0,
+            total => cost(&part.stats) as f64 / total as f64 * 100.0,
+        };
+        println!("  {:>15} {:>7.2}%  {}", cost(&part.stats), share, part.name);
+    }
+}
+
+/// Prints the functions with the highest inclusive cost, together with their most expensive
+/// callees.
+fn print_call_graph(functions: &[FunctionCosts], count: usize) {
+    let inclusive = |function: &FunctionCosts| {
+        function
+            .inclusive
+            .as_ref()
+            .unwrap_or(&function.self_cost)
+            .instruction_reads
+    };
+    let mut functions = functions.iter().collect::<Vec<_>>();
+    functions.sort_by_key(|function| Reverse(inclusive(function)));
+
+    println!("  Call graph (instructions):");
+    println!("  {:>15} {:>15}  Function", "Inclusive", "Self");
+    for function in functions.iter().take(count) {
+        println!(
+            "  {:>15} {:>15}  {}",
+            inclusive(function),
+            function.self_cost.instruction_reads,
+            function.name
+        );
+
+        let mut calls = function.calls.iter().collect::<Vec<_>>();
+        calls.sort_by_key(|call| Reverse(call.inclusive.instruction_reads));
+        for call in calls.iter().take(CALL_GRAPH_CALLEES) {
+            println!(
+                "  {:>15} {:>15}    -> {} ({} calls)",
+                call.inclusive.instruction_reads, "", call.callee, call.count
+            );
+        }
+    }
+}
+
+/// Prints the functions with the highest self cost.
+fn print_top_functions(functions: &[FunctionCosts], count: usize) {
+    let mut functions = functions.iter().collect::<Vec<_>>();
+    functions.sort_by_key(|function| Reverse(function.self_cost.instruction_reads));
+
+    println!("  Top functions (instructions):");
+    println!("  {:>15}  Function", "Self");
+    for function in functions.iter().take(count) {
+        println!(
+            "  {:>15}  {}",
+            function.self_cost.instruction_reads, function.name
+        );
+    }
+}
+
+/// Prints the functions whose self cost changed the most compared to the old results, including
+/// the functions that were added or removed.
+fn print_changed_functions(
+    functions: &[FunctionCosts],
+    old_functions: &[FunctionCosts],
+    count: usize,
+) {
+    let changes = changed_functions(functions, old_functions);
+    if changes.is_empty() {
+        return;
+    }
+
+    println!("  Changed functions (instructions):");
+    println!("  {:>15} {:>15} {:>15}  Function", "Old", "New", "Change");
+    for (name, old, new) in changes.iter().take(count) {
+        println!(
+            "  {:>15} {:>15} {:>+15}  {}",
+            old,
+            new,
+            *new as i128 - *old as i128,
+            name
+        );
+    }
+}
+
+/// Returns the name, old and new instructions read of the functions whose self cost changed,
+/// from the largest change to the smallest. Functions are matched by name and file, and the ones
+/// that were added or removed have zero old or new instructions.
+fn changed_functions<'a>(
+    functions: &'a [FunctionCosts],
+    old_functions: &'a [FunctionCosts],
+) -> Vec<(&'a str, u64, u64)> {
+    fn key(function: &FunctionCosts) -> (&str, &str) {
+        (function.name.as_str(), function.file.as_str())
+    }
+
+    let mut old_costs = old_functions
+        .iter()
+        .map(|function| (key(function), function.self_cost.instruction_reads))
+        .collect::<HashMap<_, _>>();
+    let mut changes = Vec::new();
+    for function in functions {
+        let new = function.self_cost.instruction_reads;
+        let old = old_costs.remove(&key(function)).unwrap_or(0);
+        changes.push((function.name.as_str(), old, new));
+    }
+    for function in old_functions {
+        if let Some(old) = old_costs.remove(&key(function)) {
+            changes.push((function.name.as_str(), old, 0));
+        }
+    }
+    changes.retain(|(_, old, new)| old != new);
+    changes.sort_by_key(|(_, old, new)| Reverse(old.abs_diff(*new)));
+    changes
+}
+
+/// Returns the results of a benchmark as JSON, marked as `added` if the benchmark has no results
+/// in the old set when comparing two sets of saved results.
+fn json_record(
+    benchmark: BenchmarkId<'_>,
+    region: Option<&str>,
+    stats: &Stats,
+    added: bool,
+) -> json::Value {
+    let deltas = stats.old.as_ref().map(|old| {
+        json::Value::object(
+            Metric::ALL
+                .iter()
+                .copied()
+                .filter(|metric| metric.is_measured(stats.tool, &stats.config))
+                .map(|metric| {
+                    let change = percentage_change(
+                        metric.value(&stats.new, &stats.config.cycle_model),
+                        metric.value(old, &stats.config.cycle_model),
+                    );
+                    (metric.name(), json::Value::from(change))
+                }),
+        )
+    });
+
+    let cycle_model = &stats.config.cycle_model;
+    let fields = [
+        ("id", benchmark.region_id(region).into()),
+        ("group", benchmark.group.into()),
+        ("name", benchmark.name.into()),
+        ("region", region.into()),
+        ("tool", stats.tool.name().into()),
+        ("config", json_config(&stats.config)),
+        ("stats", json_costs(&stats.new, cycle_model)),
+        (
+            "baseline",
+            stats
+                .old
+                .as_ref()
+                .map(|old| json_costs(old, cycle_model))
+                .into(),
+        ),
+        ("deltas", deltas.into()),
+        (
+            "functions",
+            stats
+                .functions
+                .iter()
+                .map(|function| json_function(function, cycle_model))
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+        (
+            "parts",
+            stats
+                .parts
+                .iter()
+                .map(|part| {
+                    json::Value::object([
+                        ("name", part.name.as_str().into()),
+                        ("stats", json_costs(&part.stats, cycle_model)),
+                    ])
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+    ]
+    .into_iter()
+    .chain(added.then(|| ("added", true.into())));
+    json::Value::object(fields)
+}
+
+pub(crate) fn json_config(config: &MeasurementConfig) -> json::Value {
+    // The environment variables are omitted if there are none, like in the configurations
+    // recorded by previous versions of Iai
+    let envs = json::Value::object(
+        config
+            .envs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str().into())),
+    );
+    let fields = [
+        ("i1_cache", config.i1_cache.to_string().into()),
+        ("d1_cache", config.d1_cache.to_string().into()),
+        ("ll_cache", config.ll_cache.to_string().into()),
+        ("cycle_model", config.cycle_model.to_string().into()),
+        ("branch_sim", config.branch_sim.into()),
+        ("trace_children", config.trace_children.into()),
+    ]
+    .into_iter()
+    .chain((!config.envs.is_empty()).then_some(("envs", envs)))
+    .chain(
+        config
+            .command
+            .map(|command| ("command", format!("{command:016x}").into())),
+    );
+    json::Value::object(fields)
+}
+
+fn json_function(function: &FunctionCosts, cycle_model: &CycleModel) -> json::Value {
+    let calls = function
+        .calls
+        .iter()
+        .map(|call| {
+            json::Value::object([
+                ("callee", call.callee.as_str().into()),
+                ("callee_file", call.callee_file.as_str().into()),
+                ("count", call.count.into()),
+                ("inclusive", json_stats(&call.inclusive, cycle_model)),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    json::Value::object([
+        ("name", function.name.as_str().into()),
+        ("file", function.file.as_str().into()),
+        ("self", json_stats(&function.self_cost, cycle_model)),
+        (
+            "inclusive",
+            function
+                .inclusive
+                .as_ref()
+                .map(|inclusive| json_stats(inclusive, cycle_model))
+                .into(),
+        ),
+        ("calls", calls.into()),
+    ])
+}
+
+/// Returns the costs measured by any tool as JSON.
+pub(crate) fn json_costs(costs: &Costs, cycle_model: &CycleModel) -> json::Value {
+    match costs {
+        Costs::Cachegrind(stats) => json_stats(stats, cycle_model),
+        Costs::Dhat(stats) => json::Value::object([
+            ("allocations", stats.allocations.into()),
+            ("allocated_bytes", stats.allocated_bytes.into()),
+            ("peak_live_bytes", stats.peak_live_bytes.into()),
+        ]),
+    }
+}
+
+pub(crate) fn json_stats(stats: &CachegrindStats, cycle_model: &CycleModel) -> json::Value {
+    let summary = stats.summarize();
+    json::Value::object([
+        ("instruction_reads", stats.instruction_reads.into()),
+        ("instruction_l1_misses", stats.instruction_l1_misses.into()),
+        (
+            "instruction_cache_misses",
+            stats.instruction_cache_misses.into(),
+        ),
+        ("data_reads", stats.data_reads.into()),
+        ("data_l1_read_misses", stats.data_l1_read_misses.into()),
+        (
+            "data_cache_read_misses",
+            stats.data_cache_read_misses.into(),
+        ),
+        ("data_writes", stats.data_writes.into()),
+        ("data_l1_write_misses", stats.data_l1_write_misses.into()),
+        (
+            "data_cache_write_misses",
+            stats.data_cache_write_misses.into(),
+        ),
+        ("conditional_branches", stats.conditional_branches.into()),
+        (
+            "conditional_branch_misses",
+            stats.conditional_branch_misses.into(),
+        ),
+        ("indirect_branches", stats.indirect_branches.into()),
+        (
+            "indirect_branch_misses",
+            stats.indirect_branch_misses.into(),
+        ),
+        (
+            "summary",
+            json::Value::object([
+                ("l1_hits", summary.l1_hits.into()),
+                ("l3_hits", summary.l3_hits.into()),
+                ("ram_hits", summary.ram_hits.into()),
+                ("branch_misses", summary.branch_misses.into()),
+                ("estimated_cycles", summary.cycles(cycle_model).into()),
+            ]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputFormat;
+    use super::Reporter;
+    use super::changed_functions;
+    use super::percentage_change;
+    use crate::Config;
+    use crate::Metric;
+    use crate::Tool;
+    use crate::cachegrind::CachegrindStats;
+    use crate::cachegrind::FunctionCosts;
+    use crate::cachegrind::parse_cachegrind;
+    use crate::registry::BenchmarkId;
+    use crate::results::Costs;
+    use crate::results::Stats;
+
+    const FIB: BenchmarkId<'static> = BenchmarkId {
+        group: Some("group"),
+        name: "fib",
+    };
+
+    fn functions(output: &str) -> Vec<FunctionCosts> {
+        parse_cachegrind(output.as_bytes()).unwrap().functions
+    }
+
+    /// Returns the stats of a benchmark that only read instructions, which are also counted as L1
+    /// accesses and cycles.
+    fn stats(new: u64, old: Option<u64>) -> Stats {
+        let instructions = |n| Costs::Cachegrind(CachegrindStats::from_events(&["Ir"], &[n]));
+        Stats {
+            new: instructions(new),
+            old: old.map(instructions),
+            tool: Tool::Cachegrind,
+            config: Config::new().measurement(),
+            regions: Vec::new(),
+            functions: Vec::new(),
+            old_functions: Vec::new(),
+            parts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn percentage_changes() {
+        assert_eq!(percentage_change(110, 100), Some(10.0));
+        assert_eq!(percentage_change(50, 100), Some(-50.0));
+        assert_eq!(percentage_change(0, 0), Some(0.0));
+        assert_eq!(percentage_change(0, 10), Some(-100.0));
+        assert_eq!(percentage_change(10, 0), None);
+    }
+
+    #[test]
+    fn markdown_marks_changes_over_thresholds() {
+        let mut reporter = Reporter::new(OutputFormat::Markdown);
+        let thresholds = [(Metric::Instructions, 20.0), (Metric::EstimatedCycles, 5.0)];
+
+        // Metrics without a threshold are marked on any change
+        let section =
+            reporter.markdown_section(FIB, None, &stats(110, Some(100)), &thresholds, false);
+        assert_eq!(
+            section,
+            "#### `group/fib`\n\n\
+             | Metric | Current | Baseline | Change |\n\
+             | :--- | ---: | ---: | ---: |\n\
+             | Instructions | 110 | 100 | +10.00% |\n\
+             | L1 Accesses | 110 | 100 | +10.00% \u{1f534} |\n\
+             | L2 Accesses | 0 | 0 | No change |\n\
+             | RAM Accesses | 0 | 0 | No change |\n\
+             | Estimated Cycles | 110 | 100 | +10.00% \u{1f534} |\n\n"
+        );
+
+        let section =
+            reporter.markdown_section(FIB, Some("sort"), &stats(70, Some(100)), &thresholds, false);
+        assert!(section.starts_with("#### `group/fib/sort`\n\n"));
+        assert!(section.contains("| Instructions | 70 | 100 | -30.00% \u{1f7e2} |\n"));
+        assert!(section.contains("| L1 Accesses | 70 | 100 | -30.00% \u{1f7e2} |\n"));
+
+        let section = reporter.markdown_section(FIB, None, &stats(100, Some(100)), &[], false);
+        assert!(section.contains("| Instructions | 100 | 100 | No change |\n"));
+
+        assert_eq!(
+            (
+                reporter.benchmarks,
+                reporter.regressions,
+                reporter.improvements
+            ),
+            (3, 1, 1)
+        );
+    }
+
+    #[test]
+    fn markdown_increases_from_zero() {
+        let mut reporter = Reporter::new(OutputFormat::Markdown);
+        let thresholds = [(Metric::Instructions, 1000.0)];
+        let section = reporter.markdown_section(FIB, None, &stats(5, Some(0)), &thresholds, false);
+        // An increase from zero has no percentage, and exceeds any threshold
+        assert!(section.contains("| Instructions | 5 | 0 | Was zero \u{1f534} |\n"));
+        assert!(section.contains("| L2 Accesses | 0 | 0 | No change |\n"));
+        assert_eq!((reporter.regressions, reporter.improvements), (1, 0));
+    }
+
+    #[test]
+    fn markdown_without_baseline() {
+        let mut reporter = Reporter::new(OutputFormat::Markdown);
+        let section = reporter.markdown_section(FIB, None, &stats(110, None), &[], true);
+        assert_eq!(
+            section,
+            "#### `group/fib`\n\n\
+             Added.\n\n\
+             | Metric | Current | Baseline | Change |\n\
+             | :--- | ---: | ---: | ---: |\n\
+             | Instructions | 110 | | |\n\
+             | L1 Accesses | 110 | | |\n\
+             | L2 Accesses | 0 | | |\n\
+             | RAM Accesses | 0 | | |\n\
+             | Estimated Cycles | 110 | | |\n\n"
+        );
+        assert_eq!(
+            (
+                reporter.benchmarks,
+                reporter.regressions,
+                reporter.improvements
+            ),
+            (1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn changed_functions_against_baseline() {
+        let old = functions(
+            "events: Ir\n\
+             fl=a.rs\nfn=same\n1 10\nfn=faster\n2 50\nfn=removed\n3 5\n\
+             fl=b.rs\nfn=same\n1 20\n\
+             summary: 85\n",
+        );
+        let new = functions(
+            "events: Ir\n\
+             fl=a.rs\nfn=same\n1 10\nfn=faster\n2 20\nfn=added\n4 40\n\
+             fl=b.rs\nfn=same\n1 21\n\
+             summary: 91\n",
+        );
+
+        assert_eq!(
+            changed_functions(&new, &old),
+            [
+                ("added", 0, 40),
+                ("faster", 50, 20),
+                ("removed", 5, 0),
+                ("same", 20, 21),
+            ]
+        );
+        assert!(changed_functions(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,461 @@
+//! Results of the benchmarks, and the files where valgrind stores them.
+
+use crate::Error;
+use crate::Tool;
+use crate::cachegrind::CachegrindStats;
+use crate::cachegrind::FunctionCosts;
+use crate::cachegrind::parse_cachegrind_output;
+use crate::callgrind::parse_callgrind_output;
+use crate::config::Config;
+use crate::config::MeasurementConfig;
+use crate::dhat;
+use crate::dhat::DhatStats;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Costs measured by a valgrind tool.
+#[derive(Clone, Debug)]
+pub(crate) enum Costs {
+    /// Costs measured by cachegrind or callgrind.
+    Cachegrind(CachegrindStats),
+    /// Heap statistics measured by DHAT.
+    Dhat(DhatStats),
+}
+
+impl Costs {
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        match (self, other) {
+            (Self::Cachegrind(a), Self::Cachegrind(b)) => Self::Cachegrind(a.add(b)),
+            (Self::Dhat(a), Self::Dhat(b)) => Self::Dhat(a.add(b)),
+            _ => panic!("costs measured by different tools"),
+        }
+    }
+
+    pub(crate) fn subtract(&self, calibration: &Self) -> Self {
+        match (self, calibration) {
+            (Self::Cachegrind(a), Self::Cachegrind(b)) => Self::Cachegrind(a.subtract(b)),
+            (Self::Dhat(a), Self::Dhat(b)) => Self::Dhat(a.subtract(b)),
+            _ => panic!("costs measured by different tools"),
+        }
+    }
+
+    /// Returns the names of the costs lower than the corresponding costs of `calibration`, which
+    /// [`subtract()`](Self::subtract) clamps to zero.
+    pub(crate) fn saturated_by(&self, calibration: &Self) -> Vec<&'static str> {
+        match (self, calibration) {
+            (Self::Cachegrind(a), Self::Cachegrind(b)) => a.saturated_by(b),
+            (Self::Dhat(a), Self::Dhat(b)) => a.saturated_by(b),
+            _ => panic!("costs measured by different tools"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Stats {
+    pub(crate) new: Costs,
+    pub(crate) old: Option<Costs>,
+    /// Tool used to obtain the results, which determines the metrics available.
+    pub(crate) tool: Tool,
+    /// Options used to obtain the results.
+    pub(crate) config: MeasurementConfig,
+    /// Names of the regions measured with [`Iai::measure()`](crate::Iai::measure), in the order
+    /// they were first run. Only available when the whole benchmark is run.
+    pub(crate) regions: Vec<String>,
+    /// Per-function costs of the new results.
+    pub(crate) functions: Vec<FunctionCosts>,
+    /// Per-function costs of the old results, if any.
+    pub(crate) old_functions: Vec<FunctionCosts>,
+    /// Costs of every process or thread of the new results, if they were measured separately.
+    pub(crate) parts: Vec<Part>,
+}
+
+impl Stats {
+    pub(crate) fn subtract(&self, other: &Self) -> Self {
+        let new = self.new.subtract(&other.new);
+        // The previous results can only be calibrated with the previous results of the
+        // calibration, otherwise they are not compared
+        let old = match (&self.old, &other.old) {
+            (Some(a), Some(b)) => Some(a.subtract(b)),
+            _ => None,
+        };
+        // Per-function costs are not adjusted: the calibration costs are all in functions that are
+        // not part of the benchmark
+        Self {
+            new,
+            old,
+            tool: self.tool,
+            config: self.config.clone(),
+            regions: self.regions.clone(),
+            functions: self.functions.clone(),
+            old_functions: self.old_functions.clone(),
+            parts: self.parts.clone(),
+        }
+    }
+}
+
+/// Costs of a single process or thread of a benchmark.
+#[derive(Clone, Debug)]
+pub(crate) struct Part {
+    /// Description of the process or thread, like `process 2, thread 1`.
+    pub(crate) name: String,
+    pub(crate) stats: Costs,
+}
+
+/// Returns the directory where the results of the benchmarks are stored.
+pub(crate) fn iai_dir() -> PathBuf {
+    let target_dir =
+        PathBuf::from(env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into()));
+    target_dir.join("iai")
+}
+
+/// Returns the name of the output file of a region of a benchmark measured in a separate run,
+/// given the name of the output file of the benchmark. Every region has its own directory, so
+/// that the files stored next to the results of a region can't clash with the results of the
+/// benchmark or of other regions.
+pub(crate) fn region_file_name(file_name: &str, region: &str) -> String {
+    format!("{file_name}.regions.d/{region}/out")
+}
+
+/// Returns the path of the file where the measurement configuration of an output file is stored.
+pub(crate) fn config_file(output_file: &Path) -> PathBuf {
+    let mut config_file = output_file.to_owned().into_os_string();
+    config_file.push(".config");
+    PathBuf::from(config_file)
+}
+
+/// Returns the path of the file where the output of valgrind and of the benchmark is saved.
+pub(crate) fn log_file(output_file: &Path) -> PathBuf {
+    let mut log_file = output_file.to_owned().into_os_string();
+    log_file.push(".log");
+    PathBuf::from(log_file)
+}
+
+/// Name of the first file written by callgrind in the dumps directory. The files of later dumps
+/// are named after it, followed by the number of the dump, like `out.2`.
+pub(crate) const DUMP_FILE_NAME: &str = "out";
+
+/// Returns the path of the directory where callgrind writes the costs of the regions of a
+/// benchmark, when processes and threads are not measured separately.
+pub(crate) fn dumps_dir(output_file: &Path) -> PathBuf {
+    let mut dumps_dir = output_file.to_owned().into_os_string();
+    dumps_dir.push(".dumps");
+    PathBuf::from(dumps_dir)
+}
+
+/// Returns the path of the directory where the results of each process or thread are stored,
+/// when they're measured separately.
+pub(crate) fn parts_dir(output_file: &Path) -> PathBuf {
+    let mut parts_dir = output_file.to_owned().into_os_string();
+    parts_dir.push(".parts");
+    PathBuf::from(parts_dir)
+}
+
+/// Returns whether the results in `output_file` were obtained with the given measurement
+/// configuration.
+pub(crate) fn same_measurement(output_file: &Path, measurement: &MeasurementConfig) -> bool {
+    stored_measurement(output_file).as_ref() == Some(measurement)
+}
+
+/// Returns the measurement configuration stored alongside the results in `output_file`, or
+/// `None` if it cannot be read. Results stored without a configuration (by older versions of
+/// Iai) are assumed to use the default configuration.
+pub(crate) fn stored_measurement(output_file: &Path) -> Option<MeasurementConfig> {
+    match fs::read_to_string(config_file(output_file)) {
+        Ok(stored) => stored.parse::<MeasurementConfig>().ok(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Some(Config::new().measurement()),
+        Err(_) => None,
+    }
+}
+
+/// Results parsed from the output files of a benchmark.
+pub(crate) struct Output {
+    pub(crate) stats: Costs,
+    pub(crate) functions: Vec<FunctionCosts>,
+    /// Costs of every process or thread, if they were measured separately.
+    pub(crate) parts: Vec<Part>,
+    /// Results of every region measured in the same run as the whole benchmark, by name. Only
+    /// callgrind measures regions in this way.
+    pub(crate) regions: Vec<(String, Output)>,
+}
+
+/// Accumulates the results of the output files of a benchmark, or of a region of a benchmark.
+#[derive(Default)]
+struct OutputBuilder {
+    total: Option<Costs>,
+    functions: Vec<FunctionCosts>,
+    parts: Vec<Part>,
+}
+
+impl OutputBuilder {
+    fn add(&mut self, part: Option<String>, stats: &Costs, functions: &[FunctionCosts]) {
+        self.total = Some(match &self.total {
+            Some(total) => total.add(stats),
+            None => stats.clone(),
+        });
+        for function in functions {
+            match self
+                .functions
+                .iter_mut()
+                .find(|f| f.name == function.name && f.file == function.file)
+            {
+                Some(existing) => existing.merge(function),
+                None => self.functions.push(function.clone()),
+            }
+        }
+        if let Some(name) = part {
+            // Costs dumped more than once by the same process or thread are added together
+            match self.parts.iter_mut().find(|part| part.name == name) {
+                Some(part) => part.stats = part.stats.add(stats),
+                None => self.parts.push(Part {
+                    name,
+                    stats: stats.clone(),
+                }),
+            }
+        }
+    }
+
+    fn build(self) -> Option<Output> {
+        Some(Output {
+            stats: self.total?,
+            functions: self.functions,
+            parts: self.parts,
+            regions: Vec::new(),
+        })
+    }
+}
+
+/// Parses the results of a benchmark stored in `output_file` by the given tool, or in the
+/// directory next to it if processes or threads were measured separately.
+///
+/// Callgrind writes the costs of every region to a separate file, and the costs of the whole
+/// benchmark are split among all the files.
+pub(crate) fn parse_output(tool: Tool, output_file: &Path) -> Result<Output, Error> {
+    // Every file is identified by its process ID, thread number and dump number, in the same
+    // order as they were written
+    let mut files = Vec::new();
+    let parts_dir = parts_dir(output_file);
+    let separate_parts = parts_dir.is_dir();
+    if separate_parts {
+        // Files are named `<pid>`, or `<pid>-<thread>` when threads are measured separately,
+        // with the dump number after the process ID for all the dumps but the first one
+        for entry in fs::read_dir(&parts_dir).map_err(|err| Error::io(&parts_dir, err))? {
+            let entry = entry.map_err(|err| Error::io(&parts_dir, err))?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let (name, thread) = match file_name.split_once('-') {
+                Some((name, thread)) => (name, Some(thread)),
+                None => (file_name, None),
+            };
+            let (pid, dump) = match name.split_once('.') {
+                Some((pid, dump)) => (pid, Some(dump)),
+                None => (name, None),
+            };
+            let (Ok(pid), Ok(thread), Ok(dump)) = (
+                pid.parse::<u32>(),
+                thread.map(str::parse::<u32>).transpose(),
+                dump.map(str::parse::<u32>).transpose(),
+            ) else {
+                continue;
+            };
+            files.push((pid, thread, dump.unwrap_or(1), entry.path()));
+        }
+    } else {
+        files.push((0, None, 1, output_file.to_owned()));
+        let dumps_dir = dumps_dir(output_file);
+        if dumps_dir.is_dir() {
+            for entry in fs::read_dir(&dumps_dir).map_err(|err| Error::io(&dumps_dir, err))? {
+                let entry = entry.map_err(|err| Error::io(&dumps_dir, err))?;
+                let file_name = entry.file_name();
+                let Some(Ok(dump)) = file_name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(DUMP_FILE_NAME)?.strip_prefix('.'))
+                    .map(str::parse::<u32>)
+                else {
+                    continue;
+                };
+                files.push((0, None, dump, entry.path()));
+            }
+        }
+    }
+    files.sort();
+
+    // Process IDs change from run to run, so processes are numbered in the order they were
+    // started instead
+    let multiple_processes = files.windows(2).any(|w| w[0].0 != w[1].0);
+    let mut process = 0;
+    let mut last_pid = None;
+    let mut total = OutputBuilder::default();
+    let mut regions: Vec<(String, OutputBuilder)> = Vec::new();
+    for (pid, thread, _, file) in files {
+        if last_pid != Some(pid) {
+            process += 1;
+            last_pid = Some(pid);
+        }
+        let (stats, functions, region) = parse_output_file(tool, &file)?;
+
+        let part = separate_parts.then(|| match (multiple_processes, thread) {
+            (true, Some(thread)) => format!("process {process}, thread {thread}"),
+            (false, Some(thread)) => format!("thread {thread}"),
+            (_, None) => format!("process {process}"),
+        });
+        total.add(part.clone(), &stats, &functions);
+        if let Some(region) = region {
+            let index = match regions.iter().position(|(name, _)| *name == region) {
+                Some(index) => index,
+                None => {
+                    regions.push((region, OutputBuilder::default()));
+                    regions.len() - 1
+                }
+            };
+            regions[index].1.add(part, &stats, &functions);
+        }
+    }
+
+    let mut output = total.build().ok_or_else(|| Error::Parse {
+        path: parts_dir.clone(),
+        line: None,
+        message: "No output files found".to_owned(),
+    })?;
+    output.regions = regions
+        .into_iter()
+        .filter_map(|(name, region)| Some((name, region.build()?)))
+        .collect();
+    Ok(output)
+}
+
+/// Parses a single output file produced by the given tool, returning the total costs, the
+/// per-function costs (if available) and the name of the region whose costs were dumped to the
+/// file, if any.
+fn parse_output_file(
+    tool: Tool,
+    file: &Path,
+) -> Result<(Costs, Vec<FunctionCosts>, Option<String>), Error> {
+    match tool {
+        Tool::Cachegrind => parse_cachegrind_output(file)
+            .map(|output| (Costs::Cachegrind(output.stats), output.functions, None))
+            .map_err(|err| Error::parse(file, err)),
+        Tool::Callgrind => parse_callgrind_output(file)
+            .map(|output| {
+                let region = output.region().map(str::to_owned);
+                (Costs::Cachegrind(output.stats), output.functions, region)
+            })
+            .map_err(|err| Error::parse(file, err)),
+        Tool::Dhat => dhat::parse_dhat_output(file)
+            .map(|stats| (Costs::Dhat(stats), Vec::new(), None))
+            .map_err(|err| Error::parse_dhat(file, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Costs;
+    use super::dumps_dir;
+    use super::parse_output;
+    use super::parts_dir;
+    use crate::Tool;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// Returns an empty directory for the output files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("iai-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the contents of a callgrind output file dumped with the given trigger, with the
+    /// given instructions read in function `f` of `lib.rs`.
+    fn dump(trigger: &str, instructions: u64) -> String {
+        format!(
+            "events: Ir\ndesc: Trigger: {trigger}\nfl=(1) lib.rs\nfn=(1) f\n1 {instructions}\n\
+             summary: {instructions}\n"
+        )
+    }
+
+    fn instructions(costs: &Costs) -> u64 {
+        match costs {
+            Costs::Cachegrind(stats) => stats.instruction_reads,
+            Costs::Dhat(_) => panic!("unexpected DHAT costs"),
+        }
+    }
+
+    #[test]
+    fn callgrind_dumps_are_merged() {
+        let dir = test_dir("dumps");
+        let output_file = dir.join("callgrind.out.bench");
+        let dumps_dir = dumps_dir(&output_file);
+        fs::create_dir_all(&dumps_dir).unwrap();
+        fs::write(&output_file, dump("Client Request: iai-region:a", 1)).unwrap();
+        fs::write(
+            dumps_dir.join("out.2"),
+            dump("Client Request: iai-region:b", 10),
+        )
+        .unwrap();
+        fs::write(
+            dumps_dir.join("out.3"),
+            dump("Client Request: iai-region:a", 100),
+        )
+        .unwrap();
+        fs::write(dumps_dir.join("out.10"), dump("Program termination", 1000)).unwrap();
+        fs::write(dumps_dir.join("unrelated"), "").unwrap();
+
+        let output = parse_output(Tool::Callgrind, &output_file).unwrap();
+        assert_eq!(instructions(&output.stats), 1111);
+        assert_eq!(output.functions.len(), 1);
+        assert_eq!(output.functions[0].self_cost.instruction_reads, 1111);
+        assert!(output.parts.is_empty());
+        let regions = output
+            .regions
+            .iter()
+            .map(|(name, output)| (name.as_str(), instructions(&output.stats)))
+            .collect::<Vec<_>>();
+        assert_eq!(regions, [("a", 101), ("b", 10)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn callgrind_parts_are_merged() {
+        let dir = test_dir("parts");
+        let output_file = dir.join("callgrind.out.bench");
+        let parts_dir = parts_dir(&output_file);
+        fs::create_dir_all(&parts_dir).unwrap();
+        let files = [
+            ("200-01", dump("Program termination", 1)),
+            ("200.2-01", dump("Program termination", 2)),
+            ("200-02", dump("Program termination", 10)),
+            ("300-01", dump("Client Request: iai-region:a", 100)),
+        ];
+        for (name, contents) in files {
+            fs::write(parts_dir.join(name), contents).unwrap();
+        }
+
+        let output = parse_output(Tool::Callgrind, &output_file).unwrap();
+        assert_eq!(instructions(&output.stats), 113);
+        let parts = output
+            .parts
+            .iter()
+            .map(|part| (part.name.as_str(), instructions(&part.stats)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            [
+                ("process 1, thread 1", 3),
+                ("process 1, thread 2", 10),
+                ("process 2, thread 1", 100),
+            ]
+        );
+        assert_eq!(output.regions.len(), 1);
+        assert_eq!(instructions(&output.regions[0].1.stats), 100);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,774 @@
+//! Runs the benchmarks and the calibrations under valgrind, and reports their results.
+
+use crate::Args;
+use crate::Command;
+use crate::Config;
+use crate::Error;
+use crate::Tool;
+use crate::UserBenchmarks;
+use crate::history;
+use crate::history::History;
+use crate::registry::BenchmarkFn;
+use crate::registry::UserBenchmark;
+use crate::regression::find_regressions;
+use crate::regression::regression_thresholds;
+use crate::regression::report_regressions;
+use crate::report::Reporter;
+use crate::results::Costs;
+use crate::results::DUMP_FILE_NAME;
+use crate::results::Stats;
+use crate::results::config_file;
+use crate::results::dumps_dir;
+use crate::results::iai_dir;
+use crate::results::log_file;
+use crate::results::parse_output;
+use crate::results::parts_dir;
+use crate::results::region_file_name;
+use crate::results::same_measurement;
+use crate::valgrind::Valgrind;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+
+#[derive(Clone, Debug)]
+pub(crate) enum Benchmark {
+    User(String),
+    Command(String, Command),
+    Calibration,
+}
+
+impl Benchmark {
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Self::User(name) | Self::Command(name, _) => name,
+            Self::Calibration => "::iai::calibration",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct BenchRunner {
+    executable: OsString,
+    tool: Tool,
+    allow_aslr: bool,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+}
+
+impl BenchRunner {
+    fn new<S: AsRef<OsStr>>(executable: S) -> Self {
+        Self {
+            executable: executable.as_ref().to_owned(),
+            tool: Tool::default(),
+            allow_aslr: false,
+            save_baseline: None,
+            baseline: None,
+        }
+    }
+
+    /// Sets the valgrind tool for the benchmarks that do not specify it in their configuration.
+    fn tool(&mut self, tool: Tool) -> &mut Self {
+        self.tool = tool;
+        self
+    }
+
+    /// Sets whether ASLR is allowed for the benchmarks that do not specify it in their
+    /// configuration.
+    fn allow_aslr(&mut self, allow_aslr: bool) -> &mut Self {
+        self.allow_aslr = allow_aslr;
+        self
+    }
+
+    /// Returns the name of the calibration of the benchmarks run with the given configuration.
+    fn calibration_name(&self, config: &Config) -> String {
+        calibration_name(
+            config,
+            config.tool.unwrap_or(self.tool),
+            config.allow_aslr.unwrap_or(self.allow_aslr),
+        )
+    }
+
+    /// Sets the name of the baseline where the results are saved.
+    ///
+    /// If set, the results are compared against the previous results saved in the same
+    /// baseline, unless a different baseline to compare against is set with
+    /// [`baseline()`](Self::baseline).
+    fn save_baseline(&mut self, save_baseline: Option<String>) -> &mut Self {
+        self.save_baseline = save_baseline;
+        self
+    }
+
+    /// Sets the name of the baseline to compare the results against.
+    fn baseline(&mut self, baseline: Option<String>) -> &mut Self {
+        self.baseline = baseline;
+        self
+    }
+
+    /// Runs the given benchmark under valgrind, storing the results in `output_dir` (relative to
+    /// the iai directory, or to the baseline directory if results are saved to a baseline) in a
+    /// file named after the tool and `file_name`.
+    ///
+    /// If `region` is given, only the region of the benchmark with that name is measured.
+    /// Otherwise, the results of the regions measured in the same run (which is only the case for
+    /// callgrind) are returned together with the results of the whole benchmark.
+    fn run(
+        &self,
+        benchmark: &Benchmark,
+        region: Option<&str>,
+        output_dir: &Path,
+        file_name: &str,
+        config: &Config,
+    ) -> Result<(Stats, Vec<(String, Stats)>), Error> {
+        let name = benchmark.name();
+        let tool = config.tool.unwrap_or(self.tool);
+        let output_file = output_dir.join(format!("{tool}.out.{file_name}"));
+        let output_file = output_file.as_path();
+
+        let iai_dir = iai_dir();
+        let (output_file, old_file) = match (&self.save_baseline, &self.baseline) {
+            (None, None) => {
+                let output_file = iai_dir.join(output_file);
+                let old_file = {
+                    let mut old_file = output_file.clone().into_os_string();
+                    old_file.push(".old");
+                    PathBuf::from(old_file)
+                };
+
+                // Renaming a directory fails if the destination exists
+                let (old_parts_dir, old_dumps_dir) = (parts_dir(&old_file), dumps_dir(&old_file));
+                for dir in [&old_parts_dir, &old_dumps_dir] {
+                    match fs::remove_dir_all(dir) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => warn!("Failed to remove {}: {}", dir.display(), err),
+                    }
+                }
+
+                // If this benchmark was already run once, move the last results to .old
+                for (from, to) in [
+                    (output_file.clone(), old_file.clone()),
+                    (config_file(&output_file), config_file(&old_file)),
+                    (parts_dir(&output_file), old_parts_dir),
+                    (dumps_dir(&output_file), old_dumps_dir),
+                ] {
+                    match fs::rename(&from, &to) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => warn!(
+                            "Failed to rename {} to {}: {}",
+                            from.display(),
+                            to.display(),
+                            err
+                        ),
+                    }
+                }
+
+                (output_file, old_file)
+            }
+            (Some(save_baseline), baseline) => {
+                let new_file = iai_dir.join(save_baseline).join(output_file);
+                let old_file = iai_dir
+                    .join(baseline.as_ref().unwrap_or(save_baseline))
+                    .join(output_file);
+                (new_file, old_file)
+            }
+            (None, Some(baseline)) => {
+                // Compare without overwriting anything: the results are stored next to the
+                // baseline, with a .new extension
+                let old_file = iai_dir.join(baseline).join(output_file);
+                let new_file = {
+                    let mut new_file = old_file.clone().into_os_string();
+                    new_file.push(".new");
+                    PathBuf::from(new_file)
+                };
+                (new_file, old_file)
+            }
+        };
+
+        // Previous versions of Iai stored the calibration of every tool under the same name, which
+        // is still used for the previous results if there are none under the current name
+        let old_file = match benchmark {
+            Benchmark::Calibration if !old_file.exists() && !parts_dir(&old_file).exists() => {
+                let legacy_file = old_file.with_file_name(format!("{tool}.out.{name}"));
+                if legacy_file.exists() {
+                    legacy_file
+                } else {
+                    old_file
+                }
+            }
+            _ => old_file,
+        };
+
+        if let Some(baseline) = &self.baseline {
+            if !old_file.exists() && !parts_dir(&old_file).exists() {
+                warn!(
+                    "Benchmark {name} has no results in baseline '{baseline}' (expected at {}), \
+                     not comparing the results",
+                    old_file.display()
+                );
+            }
+        }
+
+        // The baseline file and the output file may be the same, so the baseline must be read
+        // before running the benchmark
+        let mut measurement = config.measurement();
+        if let Benchmark::Command(_, command) = benchmark {
+            measurement.command = Some(command_hash(command));
+        }
+        let old = match parse_output(tool, &old_file) {
+            Ok(_) if !same_measurement(&old_file, &measurement) => {
+                if let Some(baseline) = &self.baseline {
+                    return Err(Error::BaselineMismatch {
+                        benchmark: name.to_owned(),
+                        baseline: baseline.clone(),
+                        path: config_file(&old_file),
+                    });
+                }
+                warn!(
+                    "The configuration of benchmark {name} changed, not comparing against the \
+                     previous results"
+                );
+                None
+            }
+            Ok(output) => Some(output),
+            Err(_) => None,
+        };
+
+        // The output file may be in a subdirectory of `iai_dir` (this is the case for groups,
+        // parameterized benchmarks and baselines)
+        let output_dir = output_file.parent().expect("output file has no parent");
+        fs::create_dir_all(output_dir).map_err(|err| Error::io(output_dir, err))?;
+
+        // The benchmark writes the names of its regions to this file, if the whole benchmark is run
+        let regions_file = {
+            let mut regions_file = output_file.clone().into_os_string();
+            regions_file.push(".regions");
+            PathBuf::from(regions_file)
+        };
+        if region.is_none() {
+            match fs::remove_file(&regions_file) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::io(&regions_file, err)),
+            }
+        }
+
+        // When processes or threads are measured separately, each of them writes its results to
+        // a file in this directory, named after its process ID and thread number. Otherwise,
+        // callgrind writes the costs of every region to a separate file in the dumps directory
+        let parts_dir = parts_dir(&output_file);
+        let dumps_dir = dumps_dir(&output_file);
+        for dir in [&parts_dir, &dumps_dir] {
+            match fs::remove_dir_all(dir) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(Error::io(dir, err)),
+            }
+        }
+        let trace_children = measurement.trace_children;
+        let separate_threads = config.separate_threads.unwrap_or(false) && tool == Tool::Callgrind;
+        let out_file = if trace_children || separate_threads {
+            fs::create_dir_all(&parts_dir).map_err(|err| Error::io(&parts_dir, err))?;
+            parts_dir.join("%p")
+        } else if tool == Tool::Callgrind {
+            fs::create_dir_all(&dumps_dir).map_err(|err| Error::io(&dumps_dir, err))?;
+            dumps_dir.join(DUMP_FILE_NAME)
+        } else {
+            output_file.clone()
+        };
+
+        let log_file = log_file(&output_file);
+        let mut valgrind = Valgrind::new(tool);
+        valgrind
+            .allow_aslr(config.allow_aslr.unwrap_or(self.allow_aslr))
+            .i1_cache(measurement.i1_cache)
+            .d1_cache(measurement.d1_cache)
+            .ll_cache(measurement.ll_cache)
+            .branch_sim(measurement.branch_sim)
+            .envs(config.envs.iter().map(|(key, value)| (key, value)))
+            .extra_args(&config.valgrind_args)
+            .trace_children(trace_children)
+            .separate_threads(separate_threads)
+            .out_file(&out_file)
+            .log_file(&log_file);
+
+        let program_args = match benchmark {
+            Benchmark::Command(_, command) => {
+                valgrind
+                    .instrument_at_start(true)
+                    .envs(command.envs.iter().map(|(key, value)| (key, value)));
+                if let Some(current_dir) = &command.current_dir {
+                    valgrind.current_dir(current_dir);
+                }
+                if let Some(stdin) = &command.stdin {
+                    valgrind.stdin(stdin.clone());
+                }
+                [&command.program]
+                    .into_iter()
+                    .chain(&command.args)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            }
+            Benchmark::User(_) | Benchmark::Calibration => {
+                let region_arg = match region {
+                    Some(region) => OsString::from(format!("--iai-region={region}")),
+                    None => {
+                        let mut arg = OsString::from("--iai-regions-file=");
+                        arg.push(&regions_file);
+                        arg
+                    }
+                };
+                vec![
+                    self.executable.clone(),
+                    format!("--iai-run={name}").into(),
+                    format!("--tool={tool}").into(),
+                    region_arg,
+                ]
+            }
+        };
+
+        valgrind.run(program_args).map_err(|mut err| {
+            if let Error::ValgrindFailed { benchmark, .. } = &mut err {
+                *benchmark = Some(name.to_owned());
+            }
+            err
+        })?;
+        if !matches!(benchmark, Benchmark::Command(..)) {
+            print_warnings(&log_file, name)?;
+        }
+
+        if out_file.starts_with(&dumps_dir) {
+            // The first dump is stored in the output file, like for any other benchmark, and the
+            // dumps directory is kept only if it contains the other dumps
+            fs::rename(&out_file, &output_file).map_err(|err| Error::io(&out_file, err))?;
+            let _ = fs::remove_dir(&dumps_dir);
+        }
+
+        let output = parse_output(tool, &output_file)?;
+
+        let config_file = config_file(&output_file);
+        fs::write(&config_file, measurement.to_string())
+            .map_err(|err| Error::io(&config_file, err))?;
+
+        let regions: Vec<String> = match fs::read_to_string(&regions_file) {
+            Ok(regions) => regions.lines().map(str::to_owned).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(Error::io(&regions_file, err)),
+        };
+
+        let region_stats = regions
+            .iter()
+            .filter_map(|region| {
+                let (_, new) = output.regions.iter().find(|(name, _)| name == region)?;
+                let old = old
+                    .as_ref()
+                    .and_then(|old| old.regions.iter().find(|(name, _)| name == region))
+                    .map(|(_, old)| old);
+                let stats = Stats {
+                    new: new.stats.clone(),
+                    old: old.map(|old| old.stats.clone()),
+                    tool,
+                    config: measurement.clone(),
+                    regions: Vec::new(),
+                    functions: new.functions.clone(),
+                    old_functions: old.map(|old| old.functions.clone()).unwrap_or_default(),
+                    parts: new.parts.clone(),
+                };
+                Some((region.clone(), stats))
+            })
+            .collect();
+
+        let (old, old_functions) = match old {
+            Some(old) => (Some(old.stats), old.functions),
+            None => (None, Vec::new()),
+        };
+        let stats = Stats {
+            new: output.stats,
+            old,
+            tool,
+            config: measurement,
+            regions,
+            functions: output.functions,
+            old_functions,
+            parts: output.parts,
+        };
+        Ok((stats, region_stats))
+    }
+}
+
+/// Prints the warnings written by the benchmark harness while it ran under valgrind, such as
+/// when it's not actually measured. Its output is otherwise only saved in the log file.
+fn print_warnings(log_file: &Path, name: &str) -> Result<(), Error> {
+    let log = fs::read(log_file).map_err(|err| Error::io(log_file, err))?;
+    for warning in String::from_utf8_lossy(&log)
+        .lines()
+        .filter_map(|line| line.strip_prefix("warning: "))
+    {
+        warn!("{name}: {warning} (see {})", log_file.display());
+    }
+    Ok(())
+}
+
+pub(crate) fn run_all_benchmarks(benches: &UserBenchmarks, args: &Args) -> Result<ExitCode, Error> {
+    let executable = env::args_os().next().expect("first argument is missing");
+
+    let benches = benches
+        .iter()
+        .filter(|benchmark| args.selects(&benchmark.id()))
+        .collect::<Vec<_>>();
+    if benches.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    Valgrind::check()?;
+
+    let mut runner = BenchRunner::new(executable);
+    runner
+        .tool(args.tool.unwrap_or_default())
+        .allow_aslr(env::var_os("IAI_ALLOW_ASLR").is_some())
+        .save_baseline(args.save_baseline.clone())
+        .baseline(args.baseline.clone());
+
+    let mut reporter = Reporter::new(args.output_format);
+    reporter
+        .top_functions(args.top_functions)
+        .markdown_file(args.markdown_report.clone());
+    let mut history = History::new(
+        history::history_file(&iai_dir()),
+        args.history_label.clone(),
+    );
+
+    // The overhead of the harness depends on how the benchmarks are run, so it's measured once
+    // for every distinct configuration of the benchmark functions, together with the groups
+    // that use it
+    let mut configs: Vec<(String, &Config, Vec<Option<&str>>)> = Vec::new();
+    for benchmark in &benches {
+        if args.no_calibration || matches!(benchmark.func, BenchmarkFn::Command(_)) {
+            continue;
+        }
+        let name = runner.calibration_name(&benchmark.config);
+        match configs.iter_mut().find(|(other, _, _)| *other == name) {
+            Some((_, _, groups)) if !groups.contains(&benchmark.group) => {
+                groups.push(benchmark.group)
+            }
+            Some(_) => {}
+            None => configs.push((name, &benchmark.config, vec![benchmark.group])),
+        }
+    }
+    let mut calibrations = Vec::new();
+    for (name, config, groups) in configs {
+        let (stats, _) = runner.run(&Benchmark::Calibration, None, Path::new(""), &name, config)?;
+        let groups = groups
+            .into_iter()
+            .map(|group| match group {
+                Some(group) => format!("group {group}"),
+                None => "ungrouped benchmarks".to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        reporter.print_calibration(&format!("Calibration for {groups}"), &stats);
+        calibrations.push((name, stats));
+    }
+    let calibration_stats = |benchmark: &UserBenchmark| {
+        let name = runner.calibration_name(&benchmark.config);
+        calibrations
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|(_, stats)| stats)
+    };
+
+    // The benchmarks are run by a pool of threads, each one taking the next benchmark from
+    // `jobs` and sending back the results, which are reported in order by this thread
+    let jobs = benches
+        .iter()
+        .map(|benchmark| {
+            let benchmark_to_run = match &benchmark.func {
+                BenchmarkFn::Function(_) => Benchmark::User(benchmark.id()),
+                BenchmarkFn::Command(func) => Benchmark::Command(benchmark.id(), func()),
+            };
+            (
+                benchmark_to_run,
+                benchmark.output_dir(),
+                benchmark.name.as_str(),
+                Config::clone(&benchmark.config),
+            )
+        })
+        .collect::<Vec<_>>();
+    let next_job = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+
+    let regressions = thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..args.jobs.get().min(jobs.len()) {
+            let sender = sender.clone();
+            let (runner, jobs, next_job, cancelled) = (&runner, &jobs, &next_job, &cancelled);
+            scope.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let index = next_job.fetch_add(1, Ordering::Relaxed);
+                    let Some((benchmark, output_dir, file_name, config)) = jobs.get(index) else {
+                        break;
+                    };
+                    let result = runner
+                        .run(benchmark, None, output_dir, file_name, config)
+                        .and_then(|(stats, regions)| {
+                            // Callgrind measures the regions in the same run as the whole
+                            // benchmark, while other tools measure every region in a separate run
+                            if stats.tool == Tool::Callgrind {
+                                return Ok((stats, regions));
+                            }
+                            let regions = stats
+                                .regions
+                                .iter()
+                                .map(|region| {
+                                    let file_name = region_file_name(file_name, region);
+                                    runner
+                                        .run(
+                                            benchmark,
+                                            Some(region),
+                                            output_dir,
+                                            &file_name,
+                                            config,
+                                        )
+                                        .map(|(stats, _)| (region.clone(), stats))
+                                })
+                                .collect::<Result<Vec<_>, _>>()?;
+                            Ok((stats, regions))
+                        });
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = HashMap::new();
+        let mut regressions = Vec::new();
+        for (index, benchmark) in benches.iter().enumerate() {
+            let (id, config) = (benchmark.bench_id(), &benchmark.config);
+            reporter.print_header(id, None);
+            let result = loop {
+                if let Some(result) = pending.remove(&index) {
+                    break result;
+                }
+                let (index, result) = receiver.recv().expect("benchmark thread panicked");
+                pending.insert(index, result);
+            };
+            let (stats, regions) = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    // Let the other threads finish the benchmarks they're running, but do not
+                    // start new ones
+                    cancelled.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            };
+            // External commands are measured from start to finish, without any overhead from
+            // Iai, so they have no calibration
+            let calibration = calibration_stats(benchmark);
+            let regions = regions
+                .iter()
+                .map(|(region, stats)| (Some(region.as_str()), stats));
+            for (region, stats) in [(None, &stats)].into_iter().chain(regions) {
+                if region.is_some() {
+                    reporter.print_header(id, region);
+                }
+                // Benchmarks added after the baseline was saved have nothing to be compared
+                // against
+                let added = args.baseline.is_some() && stats.old.is_none();
+                let stats = match calibration {
+                    Some(calibration) => calibrate(&id.region_id(region), stats, calibration),
+                    None => stats.clone(),
+                };
+                let thresholds = regression_thresholds(args, id, config, region);
+                if added {
+                    reporter.print_added(id, region, &stats, &thresholds);
+                } else {
+                    reporter.print_stats(id, region, &stats, &thresholds);
+                }
+                history.push(id, region, &stats);
+                regressions.extend(find_regressions(args, id, config, region, &stats));
+            }
+        }
+        Ok(regressions)
+    })?;
+    reporter.finish()?;
+    history.save()?;
+
+    report_regressions(regressions)
+}
+
+/// Returns the name of the calibration of the benchmarks run with the given configuration, tool
+/// and ASLR setting.
+///
+/// Benchmarks share the same calibration if they are run in the same way, so the name includes a
+/// hash of all the options that affect how the harness is run, but not, for example, of their
+/// regression thresholds.
+pub(crate) fn calibration_name(config: &Config, tool: Tool, allow_aslr: bool) -> String {
+    let options = format!(
+        "{tool}\n{}{:?}\n{:?}\n{allow_aslr}\n{}",
+        config.measurement(),
+        config.envs,
+        config.valgrind_args,
+        config.separate_threads.unwrap_or(false),
+    );
+    let hash = stable_hash(options.as_bytes());
+    format!("{}-{hash:016x}", Benchmark::Calibration.name())
+}
+
+/// Returns a hash of everything that determines what an external command does, which is stored
+/// with its results so that the results of a different command are not compared.
+fn command_hash(command: &Command) -> u64 {
+    // The length of the standard input tells an empty input from no input at all
+    let mut data = format!(
+        "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n",
+        command.program,
+        command.args,
+        command.envs,
+        command.current_dir,
+        command.stdin.as_ref().map(Vec::len),
+    )
+    .into_bytes();
+    if let Some(stdin) = &command.stdin {
+        data.extend_from_slice(stdin);
+    }
+    stable_hash(&data)
+}
+
+/// FNV-1a, since unlike the hashers of the standard library its results are stable, and the
+/// hashes stored with the results must not change from one version of Rust to another.
+fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Subtracts the calibration from the results of a benchmark, or of a region of a benchmark.
+fn calibrate(id: &str, stats: &Stats, calibration: &Stats) -> Stats {
+    warn_saturated(id, &stats.new, &calibration.new);
+    if stats.old.is_some() && calibration.old.is_none() {
+        warn!(
+            "The calibration of benchmark {id} has no previous results, not comparing against the \
+             previous results"
+        );
+    }
+    stats.subtract(calibration)
+}
+
+/// Warns if any of the costs of a benchmark is lower than its calibration, since subtracting the
+/// calibration clamps them to zero. This usually means that the calibration does not match how the
+/// benchmark was run.
+pub(crate) fn warn_saturated(id: &str, stats: &Costs, calibration: &Costs) {
+    let saturated = stats.saturated_by(calibration);
+    if !saturated.is_empty() {
+        warn!(
+            "The calibration exceeds the results of benchmark {id}, which were clamped to zero: {}",
+            saturated.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::calibration_name;
+    use super::command_hash;
+    use crate::CacheGeometry;
+    use crate::Command;
+    use crate::Config;
+    use crate::Metric;
+    use crate::Tool;
+
+    #[test]
+    fn calibration_names_are_stable() {
+        // The names key the stored results of the calibration, so they must not change when the
+        // options are reordered or new options are added
+        assert_eq!(
+            calibration_name(&Config::new(), Tool::Cachegrind, false),
+            "::iai::calibration-6e15521b48e5d224"
+        );
+        let config = Config::new()
+            .tool(Tool::Callgrind)
+            .env("RUST_LOG", "off")
+            .valgrind_args(["--fair-sched=yes"])
+            .separate_threads(true);
+        assert_eq!(
+            calibration_name(&config, Tool::Callgrind, true),
+            "::iai::calibration-aab951d658c97a66"
+        );
+    }
+
+    #[test]
+    fn calibration_names_depend_on_the_options() {
+        let name = |config: &Config, tool| calibration_name(config, tool, false);
+        let default = name(&Config::new(), Tool::Cachegrind);
+        let configs = [
+            (Config::new().env("RUST_LOG", "off"), Tool::Cachegrind),
+            (Config::new().env("RUST_LOG", "debug"), Tool::Cachegrind),
+            (
+                Config::new().d1_cache(CacheGeometry::new(4096, 2, 64)),
+                Tool::Cachegrind,
+            ),
+            (
+                Config::new().ll_cache(CacheGeometry::new(65536, 4, 64)),
+                Tool::Cachegrind,
+            ),
+            (Config::new().branch_sim(true), Tool::Cachegrind),
+            (
+                Config::new().valgrind_args(["--fair-sched=yes"]),
+                Tool::Cachegrind,
+            ),
+            (Config::new(), Tool::Callgrind),
+            (Config::new(), Tool::Dhat),
+        ];
+        let mut names = vec![default.clone()];
+        for (config, tool) in &configs {
+            let name = name(config, *tool);
+            assert!(!names.contains(&name), "{config:?} {tool}");
+            names.push(name);
+        }
+        assert_ne!(
+            calibration_name(&Config::new(), Tool::Cachegrind, true),
+            default
+        );
+
+        // Options that don't affect how the harness runs share the calibration
+        let thresholds = Config::new().regression_threshold(Metric::Instructions, 5.0);
+        assert_eq!(name(&thresholds, Tool::Cachegrind), default);
+    }
+
+    #[test]
+    fn command_hash_covers_the_whole_command() {
+        let command = || Command::new("sort").arg("-r").env("LC_ALL", "C");
+        let hash = command_hash(&command());
+        assert_eq!(command_hash(&command()), hash);
+
+        let changed = [
+            Command::new("uniq").arg("-r").env("LC_ALL", "C"),
+            command().arg("-u"),
+            Command::new("sort").env("LC_ALL", "C"),
+            command().env("LANG", "C"),
+            Command::new("sort").arg("-r").env("LC_ALL", "POSIX"),
+            command().current_dir("/tmp"),
+            command().stdin("b\na\n"),
+            command().stdin(""),
+        ];
+        for command in &changed {
+            assert_ne!(command_hash(command), hash, "{command:?}");
+        }
+        assert_ne!(
+            command_hash(&command().stdin("a\n")),
+            command_hash(&command().stdin("b\n"))
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Implementation of the valgrind client requests, as defined in `valgrind.h`.
+//!
+//! Client requests are encoded as a special sequence of instructions that has no effect when
+//! running natively, but that is recognized by valgrind. The sequence is architecture-specific:
+//! on architectures that are not supported, client requests do nothing and always return the
+//! default value (zero).
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use std::arch::asm;
+
+pub(crate) const fn valgrind_request_code(a: u8, b: u8, c: u16) -> usize {
+    (a as usize) << 24 | (b as usize) << 16 | (c as usize)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) fn valgrind_request(args: &[usize; 6]) -> usize {
+    let mut result = 0_usize;
+    unsafe {
+        asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") args,
+            inout("rdx") result,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) fn valgrind_request(args: &[usize; 6]) -> usize {
+    let mut result = 0_usize;
+    unsafe {
+        asm!(
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            in("x4") args,
+            inout("x3") result,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    result
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[inline(always)]
+pub(crate) fn valgrind_request(_args: &[usize; 6]) -> usize {
+    0
+}
+
+#[inline(always)]
+pub(crate) fn running_on_valgrind() -> bool {
+    const REQ: usize = 0x1001;
+    valgrind_request(&[REQ, 0, 0, 0, 0, 0]) != 0
+}
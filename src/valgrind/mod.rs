@@ -0,0 +1,10 @@
+mod client;
+mod runner;
+
+pub(crate) use client::running_on_valgrind;
+pub(crate) use client::valgrind_request;
+pub(crate) use client::valgrind_request_code;
+pub(crate) use runner::DEFAULT_D1_CACHE;
+pub(crate) use runner::DEFAULT_I1_CACHE;
+pub(crate) use runner::DEFAULT_LL_CACHE;
+pub(crate) use runner::Valgrind;
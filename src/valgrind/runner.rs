@@ -0,0 +1,319 @@
+use crate::config::CacheGeometry;
+use crate::config::Tool;
+use crate::error::Error;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+
+// Set some reasonable cache sizes. The exact sizes matter less than having fixed sizes, since
+// otherwise valgrind would take them from the CPU and make benchmark runs even more
+// incomparable between machines.
+pub(crate) const DEFAULT_I1_CACHE: CacheGeometry = CacheGeometry::new(32768, 8, 64);
+pub(crate) const DEFAULT_D1_CACHE: CacheGeometry = CacheGeometry::new(32768, 8, 64);
+pub(crate) const DEFAULT_LL_CACHE: CacheGeometry = CacheGeometry::new(8388608, 16, 64);
+
+/// Number of lines from the end of the log file included in the errors.
+const LOG_TAIL_LINES: usize = 20;
+
+pub(crate) struct Valgrind {
+    tool: Tool,
+    out_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    instrument_at_start: bool,
+    current_dir: Option<PathBuf>,
+    stdin: Option<Vec<u8>>,
+    allow_aslr: bool,
+    i1_cache: CacheGeometry,
+    d1_cache: CacheGeometry,
+    ll_cache: CacheGeometry,
+    branch_sim: bool,
+    trace_children: bool,
+    separate_threads: bool,
+    envs: Vec<(OsString, OsString)>,
+    extra_args: Vec<OsString>,
+}
+
+impl Valgrind {
+    pub(crate) fn new(tool: Tool) -> Self {
+        Self {
+            tool,
+            out_file: None,
+            log_file: None,
+            instrument_at_start: false,
+            current_dir: None,
+            stdin: None,
+            allow_aslr: false,
+            i1_cache: DEFAULT_I1_CACHE,
+            d1_cache: DEFAULT_D1_CACHE,
+            ll_cache: DEFAULT_LL_CACHE,
+            branch_sim: false,
+            trace_children: false,
+            separate_threads: false,
+            envs: Vec::new(),
+            extra_args: Vec::new(),
+        }
+    }
+
+    pub(crate) fn allow_aslr(&mut self, allow_aslr: bool) -> &mut Self {
+        self.allow_aslr = allow_aslr;
+        self
+    }
+
+    pub(crate) fn i1_cache(&mut self, geometry: CacheGeometry) -> &mut Self {
+        self.i1_cache = geometry;
+        self
+    }
+
+    pub(crate) fn d1_cache(&mut self, geometry: CacheGeometry) -> &mut Self {
+        self.d1_cache = geometry;
+        self
+    }
+
+    pub(crate) fn ll_cache(&mut self, geometry: CacheGeometry) -> &mut Self {
+        self.ll_cache = geometry;
+        self
+    }
+
+    pub(crate) fn branch_sim(&mut self, branch_sim: bool) -> &mut Self {
+        self.branch_sim = branch_sim;
+        self
+    }
+
+    /// Sets whether the child processes of the program are measured. Each process writes its
+    /// results to a separate file, so the output file should contain `%p`, which valgrind
+    /// replaces with the process ID.
+    pub(crate) fn trace_children(&mut self, trace_children: bool) -> &mut Self {
+        self.trace_children = trace_children;
+        self
+    }
+
+    /// Sets whether each thread writes its results to a separate file, named after the output
+    /// file with a `-<thread>` suffix. Only supported by callgrind, ignored by cachegrind.
+    pub(crate) fn separate_threads(&mut self, separate_threads: bool) -> &mut Self {
+        self.separate_threads = separate_threads;
+        self
+    }
+
+    pub(crate) fn envs<I, K, V>(&mut self, envs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs.extend(
+            envs.into_iter()
+                .map(|(key, value)| (key.as_ref().to_owned(), value.as_ref().to_owned())),
+        );
+        self
+    }
+
+    pub(crate) fn extra_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.extra_args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub(crate) fn out_file<P: AsRef<Path>>(&mut self, out_file: P) -> &mut Self {
+        self.out_file = Some(out_file.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the file where the standard output and standard error of valgrind (and of the
+    /// program run by valgrind) are written.
+    pub(crate) fn log_file<P: AsRef<Path>>(&mut self, log_file: P) -> &mut Self {
+        self.log_file = Some(log_file.as_ref().to_owned());
+        self
+    }
+
+    /// Sets whether the program is measured from the start. If not, the program must enable
+    /// instrumentation itself through client requests.
+    pub(crate) fn instrument_at_start(&mut self, instrument_at_start: bool) -> &mut Self {
+        self.instrument_at_start = instrument_at_start;
+        self
+    }
+
+    pub(crate) fn current_dir<P: AsRef<Path>>(&mut self, current_dir: P) -> &mut Self {
+        self.current_dir = Some(current_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the data written to the standard input of the program. If not set, the standard
+    /// input is empty.
+    pub(crate) fn stdin(&mut self, stdin: Vec<u8>) -> &mut Self {
+        self.stdin = Some(stdin);
+        self
+    }
+
+    pub(crate) fn run<I, S>(&self, args: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut cmd = if self.allow_aslr {
+            Command::new("valgrind")
+        } else if cfg!(target_os = "linux") {
+            let mut cmd = Command::new("setarch");
+            cmd.arg("-R").arg("valgrind");
+            cmd
+        } else if cfg!(target_os = "freebsd") {
+            let mut cmd = Command::new("proccontrol");
+            cmd.arg("-m")
+                .arg("aslr")
+                .arg("-s")
+                .arg("disable")
+                .arg("valgrind");
+            cmd
+        } else {
+            // Can't disable ASLR on this platform
+            Command::new("valgrind")
+        };
+
+        let at_start = if self.instrument_at_start {
+            "yes"
+        } else {
+            "no"
+        };
+        match self.tool {
+            Tool::Cachegrind => {
+                cmd.arg("--tool=cachegrind")
+                    .arg("--cache-sim=yes")
+                    .arg(format!("--instr-at-start={at_start}"));
+            }
+            Tool::Callgrind => {
+                // Unless the whole program is measured, instrumentation and collection are
+                // enabled by the benchmark through client requests, around the code to measure
+                cmd.arg("--tool=callgrind")
+                    .arg("--cache-sim=yes")
+                    .arg(format!("--instr-atstart={at_start}"))
+                    .arg(format!("--collect-atstart={at_start}"));
+            }
+            Tool::Dhat => {
+                // DHAT has no client requests to toggle instrumentation, and does not simulate
+                // caches
+                cmd.arg("--tool=dhat");
+            }
+        }
+
+        if self.tool != Tool::Dhat {
+            cmd.arg(format!("--I1={}", self.i1_cache))
+                .arg(format!("--D1={}", self.d1_cache))
+                .arg(format!("--LL={}", self.ll_cache));
+
+            if self.branch_sim {
+                cmd.arg("--branch-sim=yes");
+            }
+        }
+        if self.trace_children {
+            cmd.arg("--trace-children=yes");
+        }
+        if self.separate_threads && self.tool == Tool::Callgrind {
+            cmd.arg("--separate-threads=yes");
+        }
+
+        if let Some(out_file) = &self.out_file {
+            // Relative paths would be resolved by valgrind from the working directory of the
+            // program
+            let out_file = match self.current_dir {
+                Some(_) => path::absolute(out_file).map_err(|err| Error::io(out_file, err))?,
+                None => out_file.clone(),
+            };
+            cmd.arg(format!("--{}-out-file={}", self.tool, out_file.display()));
+        }
+
+        cmd.args(&self.extra_args);
+        cmd.envs(self.envs.iter().map(|(key, value)| (key, value)));
+
+        for arg in args {
+            cmd.arg(arg);
+        }
+
+        if let Some(current_dir) = &self.current_dir {
+            cmd.current_dir(current_dir);
+        }
+
+        match &self.log_file {
+            Some(log_file) => {
+                let log = File::create(log_file).map_err(|err| Error::io(log_file, err))?;
+                let log_clone = log.try_clone().map_err(|err| Error::io(log_file, err))?;
+                cmd.stdout(log_clone).stderr(log);
+            }
+            None => {
+                cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+            }
+        }
+        cmd.stdin(match self.stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        });
+
+        // Valgrind may be started through another program, which is reported if it fails
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let mut child = cmd
+            .spawn()
+            .map_err(|source| Error::spawn(&program, source))?;
+        let writer = child.stdin.take().map(|mut stdin| {
+            let input = self.stdin.clone().unwrap_or_default();
+            thread::spawn(move || stdin.write_all(&input))
+        });
+        let out = child
+            .wait_with_output()
+            .map_err(|source| Error::spawn(&program, source))?;
+        if let Some(writer) = writer {
+            // The program may exit without reading all of its input, which is not an error
+            let _ = writer.join();
+        }
+
+        match &self.log_file {
+            Some(log_file) if !out.status.success() => {
+                let log = fs::read(log_file).map_err(|err| Error::io(log_file, err))?;
+                let log = String::from_utf8_lossy(&log);
+                let mut tail = log.lines().rev().take(LOG_TAIL_LINES).collect::<Vec<_>>();
+                tail.reverse();
+                Err(Error::ValgrindFailed {
+                    benchmark: None,
+                    status: out.status,
+                    output: tail.join("\n"),
+                    log_file: Some(log_file.clone()),
+                })
+            }
+            _ => check_status(out),
+        }
+    }
+
+    pub(crate) fn check() -> Result<(), Error> {
+        let out = Command::new("valgrind")
+            .arg("--tool=cachegrind")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|source| Error::spawn("valgrind", source))?;
+        check_status(out)
+    }
+}
+
+fn check_status(out: Output) -> Result<(), Error> {
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(Error::ValgrindFailed {
+            benchmark: None,
+            status: out.status,
+            output: String::from_utf8_lossy(&out.stderr).into_owned(),
+            log_file: None,
+        })
+    }
+}